@@ -1,42 +1,73 @@
-use std::{fs::read_to_string, io};
-
-fn main() -> io::Result<()> {
-    let file = read_to_string("input.txt")?;
-    let codes: Vec<_> = file
-        .trim()
-        .split(',')
-        .map(|line| line.parse::<usize>().unwrap())
-        .collect();
-
-    println!("Part1: {}", intcode_computer(&mut codes.clone(), 12, 2));
-    println!("Part2: {}", find_noun_word_combo(&codes));
+use intcode::{evaluate_symbolic, load, Linear, Machine};
+
+use std::{collections::VecDeque, error::Error, thread};
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let program = load("input.txt")?;
+    let machine = Machine::new(program.clone());
+
+    println!("Part1: {}", intcode_computer(&machine, 12, 2)?);
+    println!("Part2: {}", find_noun_word_combo(&program, 19690720)?);
     Ok(())
 }
 
-fn find_noun_word_combo(codes: &Vec<usize>) -> usize {
-    for noun in 0..100 {
-        for word in 0..100 {
-            if intcode_computer(&mut codes.clone(), noun, word) == 19690720 {
-                return format!("{}{}", noun, word).parse().unwrap();
-            }
-        }
-    }
-    unreachable!("Did not find any code that ended with: 19690720")
+/// `memory[0]` after running the program, if it is linear in the noun and
+/// the word.
+fn linear_output(program: &[i64]) -> Option<Linear> {
+    let memory = evaluate_symbolic(program, &[1, 2]).ok()?;
+    memory.first()?.as_ref()?.linear(2)
 }
 
-fn intcode_computer(codes: &mut [usize], noun: usize, word: usize) -> usize {
-    let mut pos = 0;
-    codes[1] = noun;
-    codes[2] = word;
-    loop {
-        match codes[pos] {
-            99 => break codes[0],
-            1 => codes[codes[pos + 3]] = codes[codes[pos + 1]] + codes[codes[pos + 2]],
-            2 => codes[codes[pos + 3]] = codes[codes[pos + 1]] * codes[codes[pos + 2]],
-            n => panic!("reached unknown code: {}", n),
-        }
-        pos += 4;
+fn find_noun_word_combo(program: &[i64], target: i64) -> Result<i64, Box<dyn Error>> {
+    let found = match linear_output(program) {
+        Some(linear) => linear
+            .solve(target, &[0..=99, 0..=99])
+            .map(|values| (values[0], values[1])),
+        None => brute_force(&Machine::new(program.to_vec()), target)?,
+    };
+    let (noun, word) = found.ok_or_else(|| format!("No noun and word give {}", target))?;
+    Ok(100 * noun + word)
+}
+
+/// Tries every noun and word, spread over a few threads.
+fn brute_force(machine: &Machine, target: i64) -> Result<Option<(i64, i64)>, Box<dyn Error>> {
+    const THREADS: i64 = 4;
+    let results: Vec<Result<Option<(i64, i64)>, String>> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..THREADS)
+            .map(|first| {
+                scope.spawn(move || {
+                    for noun in (first..100).step_by(THREADS as usize) {
+                        for word in 0..100 {
+                            let output = intcode_computer(machine, noun, word)
+                                .map_err(|err| err.to_string())?;
+                            if output == target {
+                                return Ok(Some((noun, word)));
+                            }
+                        }
+                    }
+                    Ok(None)
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .map(|worker| worker.join().expect("brute force thread panicked"))
+            .collect()
+    });
+    let mut found = Vec::new();
+    for result in results {
+        found.extend(result?);
     }
+    Ok(found.into_iter().min())
+}
+
+/// Runs a clone of `machine`, which only copies the memory pages it writes to.
+fn intcode_computer(machine: &Machine, noun: i64, word: i64) -> Result<i64, Box<dyn Error>> {
+    let mut machine = machine.clone();
+    machine.memory_mut().set(1, noun)?;
+    machine.memory_mut().set(2, word)?;
+    machine.run_to_halt(&mut VecDeque::new(), &mut Vec::new())?;
+    Ok(machine.memory()[0])
 }
 
 #[cfg(test)]
@@ -47,11 +78,30 @@ mod tests {
     fn test_name() {
         assert_eq!(
             intcode_computer(
-                &mut vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50, 1],
+                &Machine::new(vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50, 1]),
                 12,
                 2
-            ),
+            )
+            .unwrap(),
             150
         )
     }
+
+    #[test]
+    fn test_noun_word_combo() {
+        let program = load("input.txt").unwrap();
+        assert!(linear_output(&program).is_some());
+        let machine = Machine::new(program.clone());
+        let (noun, word) = brute_force(&machine, 19690720).unwrap().unwrap();
+        assert_eq!(
+            find_noun_word_combo(&program, 19690720).unwrap(),
+            100 * noun + word
+        );
+
+        // memory[0] = noun * word, which is not linear.
+        let program = [1, 0, 0, 3, 2, 1, 2, 0, 99];
+        assert!(linear_output(&program).is_none());
+        assert_eq!(find_noun_word_combo(&program, 391).unwrap(), 1723);
+        assert!(find_noun_word_combo(&program, 9999).is_err());
+    }
 }
@@ -85,7 +85,6 @@ mod tests {
     #[test]
     fn test_other_user() {
         let part1 = (134564..=585159)
-            .into_iter()
             .filter(valid_password_part1)
             .count();
         assert_eq!(part1, 1929);
@@ -18,9 +18,8 @@ fn hash_sum(map: &PlanetMap, key: &str, level: usize) -> usize {
 
 fn path_to_com(map: &PlanetMap, level: usize, target: &str, path: &mut HashMap<String, usize>) {
     path.insert(target.to_string(), level);
-    match &map[target].0 {
-        Some(t) => path_to_com(map, level +1, &t, path),
-        None => (),
+    if let Some(t) = &map[target].0 {
+        path_to_com(map, level + 1, t, path)
     }
 }
 
@@ -78,6 +77,7 @@ K)L";
             planets
                 .entry(center)
                 .or_insert_with(|| (None, HashSet::new()))
+                .1
                 .insert(planet);
         }
         println!("{:#?}", &planets);
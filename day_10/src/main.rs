@@ -181,14 +181,13 @@ fn astroid_field(map: &str) -> (HashSet<Point>, usize, usize) {
             x = line.chars().count();
             y = *n;
         })
-        .map(|(y, n)| {
+        .flat_map(|(y, n)| {
             n.chars().enumerate().filter_map(move |(x, c)| match c {
                 '#' => Some(Point::new(x, y)),
                 '.' => None,
                 s => unreachable!("Astroid fields only contains: \"#.\", got: {}", s),
             })
         })
-        .flatten()
         .collect();
     (astroids, x, y + 1)
 }
@@ -294,7 +293,7 @@ mod tests {
         let str_fields = &[t1, t2, t3, t4, t5];
         let set_fields = str_fields
             .iter()
-            .map(|n| astroid_field(*n))
+            .map(|n| astroid_field(n))
             .collect::<Vec<_>>();
         let mut n = 0;
         str_fields
@@ -0,0 +1,207 @@
+//! Compares running a fresh copy of the program in plain vector memory,
+//! the way machines ran before memory was copy-on-write, against loading a
+//! fresh machine for every run and against cloning a loaded machine, which
+//! shares memory until it is written.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use intcode::{parse_program, Event, Machine};
+
+use std::collections::VecDeque;
+
+/// A machine whose memory is a plain vector, cloned in full for every run
+/// and grown when written past its end, as before copy-on-write memory.
+/// Only supports what the day 2 and day 7 programs need, and skips the
+/// checks [`Machine`] makes on every access, so its time is a lower bound
+/// for clone-per-run rather than the old machine exactly.
+struct VecMachine {
+    memory: Vec<i64>,
+    ip: usize,
+    relative_base: i64,
+}
+
+impl VecMachine {
+    fn new(memory: Vec<i64>) -> Self {
+        Self {
+            memory,
+            ip: 0,
+            relative_base: 0,
+        }
+    }
+
+    fn read(&self, address: usize) -> i64 {
+        self.memory.get(address).copied().unwrap_or(0)
+    }
+
+    fn write(&mut self, address: usize, value: i64) {
+        if address >= self.memory.len() {
+            self.memory.resize(address + 1, 0);
+        }
+        self.memory[address] = value;
+    }
+
+    /// The address argument `n` of the current instruction refers to.
+    fn arg(&self, n: usize) -> usize {
+        let at = self.ip + n;
+        match self.read(self.ip) / [100, 1000, 10000][n - 1] % 10 {
+            0 => self.read(at) as usize,
+            1 => at,
+            _ => (self.relative_base + self.read(at)) as usize,
+        }
+    }
+
+    /// Runs until the next output, `None` once halted.
+    fn run(&mut self, inputs: &mut VecDeque<i64>) -> Option<i64> {
+        loop {
+            let opcode = self.read(self.ip) % 100;
+            let (a, b, c) = (self.arg(1), self.arg(2), self.arg(3));
+            match opcode {
+                1 => self.write(c, self.read(a) + self.read(b)),
+                2 => self.write(c, self.read(a) * self.read(b)),
+                3 => {
+                    let value = inputs.pop_front().expect("out of input");
+                    self.write(a, value);
+                }
+                4 => {
+                    self.ip += 2;
+                    return Some(self.read(a));
+                }
+                5 | 6 => {
+                    if (self.read(a) != 0) == (opcode == 5) {
+                        self.ip = self.read(b) as usize;
+                        continue;
+                    }
+                }
+                7 => self.write(c, (self.read(a) < self.read(b)) as i64),
+                8 => self.write(c, (self.read(a) == self.read(b)) as i64),
+                9 => self.relative_base += self.read(a),
+                99 => return None,
+                opcode => panic!("unknown opcode {}", opcode),
+            }
+            self.ip += match opcode {
+                1 | 2 | 7 | 8 => 4,
+                5 | 6 => 3,
+                _ => 2,
+            };
+        }
+    }
+}
+
+fn vec_day_02(program: Vec<i64>, noun: i64, verb: i64) -> i64 {
+    let mut machine = VecMachine::new(program);
+    machine.write(1, noun);
+    machine.write(2, verb);
+    while machine.run(&mut VecDeque::new()).is_some() {}
+    machine.memory[0]
+}
+
+fn vec_day_07(program: &[i64], phases: &[i64]) -> i64 {
+    let mut signal = 0;
+    for &phase in phases {
+        let mut amplifier = VecMachine::new(program.to_vec());
+        let mut inputs = VecDeque::from(vec![phase, signal]);
+        signal = amplifier.run(&mut inputs).expect("no signal");
+    }
+    signal
+}
+
+fn day_02(machine: Machine, noun: i64, verb: i64) -> i64 {
+    let mut machine = machine;
+    machine.memory_mut().set(1, noun).unwrap();
+    machine.memory_mut().set(2, verb).unwrap();
+    machine
+        .run_to_halt(&mut VecDeque::new(), &mut Vec::new())
+        .unwrap();
+    machine.memory()[0]
+}
+
+fn day_07(mut amplifiers: Vec<Machine>, phases: &[i64]) -> i64 {
+    let mut signal = 0;
+    for (amplifier, &phase) in amplifiers.iter_mut().zip(phases) {
+        amplifier.push_input(phase);
+        amplifier.push_input(signal);
+        match amplifier.run_until_event().unwrap() {
+            Event::Output(n) => signal = n,
+            event => panic!("unexpected {:?}", event),
+        }
+    }
+    signal
+}
+
+/// Every ordering of `0..n`.
+fn permutations(n: i64) -> Vec<Vec<i64>> {
+    match n {
+        0 => vec![Vec::new()],
+        _ => permutations(n - 1)
+            .into_iter()
+            .flat_map(|p| {
+                (0..n as usize).map(move |at| {
+                    let mut p = p.clone();
+                    p.insert(at, n - 1);
+                    p
+                })
+            })
+            .collect(),
+    }
+}
+
+fn bench_day_02(c: &mut Criterion) {
+    let program = parse_program(include_str!("../../day_02/input.txt")).unwrap();
+    let base = Machine::new(program.clone());
+    assert_eq!(
+        vec_day_02(program.clone(), 12, 2),
+        day_02(base.clone(), 12, 2)
+    );
+    let mut group = c.benchmark_group("day_02 100 runs");
+    group.bench_function("clone vec", |b| {
+        b.iter(|| {
+            (0..100)
+                .map(|n| vec_day_02(program.clone(), n / 10, n % 10))
+                .max()
+        })
+    });
+    group.bench_function("copy program", |b| {
+        b.iter(|| {
+            (0..100)
+                .map(|n| day_02(Machine::new(program.clone()), n / 10, n % 10))
+                .max()
+        })
+    });
+    group.bench_function("clone machine", |b| {
+        b.iter(|| (0..100).map(|n| day_02(base.clone(), n / 10, n % 10)).max())
+    });
+    group.finish();
+}
+
+fn bench_day_07(c: &mut Criterion) {
+    let program = parse_program(include_str!("../../day_07/input.txt")).unwrap();
+    let base = Machine::new(program.clone());
+    let phases = permutations(5);
+    assert_eq!(
+        vec_day_07(&program, &phases[0]),
+        day_07(vec![base.clone(); 5], &phases[0])
+    );
+    let mut group = c.benchmark_group("day_07 part 1");
+    group.bench_function("clone vec", |b| {
+        b.iter(|| phases.iter().map(|p| vec_day_07(&program, p)).max())
+    });
+    group.bench_function("copy program", |b| {
+        b.iter(|| {
+            phases
+                .iter()
+                .map(|p| day_07((0..5).map(|_| Machine::new(program.clone())).collect(), p))
+                .max()
+        })
+    });
+    group.bench_function("clone machine", |b| {
+        b.iter(|| {
+            phases
+                .iter()
+                .map(|p| day_07((0..5).map(|_| base.clone()).collect(), p))
+                .max()
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_day_02, bench_day_07);
+criterion_main!(benches);
@@ -0,0 +1,67 @@
+//! Compares decoding every instruction as it executes against running the
+//! closures a [`ThreadedMachine`] decoded once up front.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use intcode::{assemble, parse_program, Machine, ThreadedMachine};
+
+use std::collections::VecDeque;
+
+/// Sums `1..=n` in a tight loop, where decoding dominates.
+const LOOP: &str = "
+    in -> [n]
+loop:
+    add [sum], [n] -> [sum]
+    add [n], #-1 -> [n]
+    jnz [n], loop
+    out [sum]
+    hlt
+n:   .data 0
+sum: .data 0
+";
+
+fn bench(c: &mut Criterion, name: &str, program: Vec<i64>, runs: &[Vec<i64>]) {
+    let machine = Machine::new(program);
+    let threaded = ThreadedMachine::from(machine.clone());
+    let mut group = c.benchmark_group(name);
+    group.bench_function("decode every step", |b| {
+        b.iter(|| {
+            for input in runs {
+                let mut out = Vec::new();
+                machine
+                    .clone()
+                    .run_to_halt(&mut VecDeque::from(input.clone()), &mut out)
+                    .unwrap();
+            }
+        })
+    });
+    group.bench_function("pre-decoded", |b| {
+        b.iter(|| {
+            for input in runs {
+                let mut out = Vec::new();
+                threaded
+                    .clone()
+                    .run_to_halt(&mut VecDeque::from(input.clone()), &mut out)
+                    .unwrap();
+            }
+        })
+    });
+    group.finish();
+}
+
+fn bench_loop(c: &mut Criterion) {
+    bench(c, "sum loop", assemble(LOOP).unwrap(), &[vec![100_000]]);
+}
+
+fn bench_day_05(c: &mut Criterion) {
+    let program = parse_program(include_str!("../../day_05/input.txt")).unwrap();
+    bench(c, "day_05", program, &[vec![1], vec![5]]);
+}
+
+fn bench_day_07(c: &mut Criterion) {
+    let program = parse_program(include_str!("../../day_07/input.txt")).unwrap();
+    let runs: Vec<_> = (0..5).map(|phase| vec![phase, 1234]).collect();
+    bench(c, "day_07 amplifiers", program, &runs);
+}
+
+criterion_group!(benches, bench_loop, bench_day_05, bench_day_07);
+criterion_main!(benches);
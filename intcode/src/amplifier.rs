@@ -0,0 +1,403 @@
+// The impls generated by `err-derive` live inside an anonymous const.
+#![allow(non_local_definitions)]
+
+use crate::{
+    error::Error,
+    machine::{Event, Machine},
+    network::{Network, NetworkError},
+};
+
+use err_derive::Error;
+use std::thread;
+
+/// How the amplifiers of an [`AmplifierChain`] are connected.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Mode {
+    /// The signal passes through every amplifier once.
+    Linear,
+    /// The last amplifier feeds back into the first until they all halt.
+    Feedback,
+}
+
+/// Why a phase setting gave no signal.
+#[derive(Debug, Error, PartialEq, Eq, Clone)]
+pub enum ChainError {
+    #[error(display = "phases {:?}: {}", phases, error)]
+    Network {
+        phases: Vec<i64>,
+        error: NetworkError,
+    },
+    #[error(
+        display = "phases {:?}: amplifier {} failed: {}",
+        phases,
+        amplifier,
+        error
+    )]
+    Amplifier {
+        phases: Vec<i64>,
+        amplifier: usize,
+        error: Error,
+    },
+    /// An amplifier of a linear chain asked for more input than it was given.
+    #[error(
+        display = "phases {:?}: amplifier {} waits for input",
+        phases,
+        amplifier
+    )]
+    NeedsInput { phases: Vec<i64>, amplifier: usize },
+    #[error(display = "phases {:?}: the last amplifier sent no signal", phases)]
+    NoSignal { phases: Vec<i64> },
+    /// The phases repeat one, or are not all from the phase set.
+    #[error(
+        display = "phases {:?}: not different phases from the phase set",
+        phases
+    )]
+    Phases { phases: Vec<i64> },
+    #[error(display = "cannot pick {} of {} phases", amplifiers, phases)]
+    Amplifiers { amplifiers: usize, phases: usize },
+}
+
+/// A phase setting, one phase per amplifier, with the signal it gives.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Setting {
+    pub phases: Vec<i64>,
+    pub signal: i64,
+}
+
+/// A chain of amplifiers running the same program, searched for the phase
+/// setting that gives the highest signal.
+///
+/// Every amplifier gets a different phase from the phase set as its first
+/// input, and the first amplifier gets the signal 0 as its second. The
+/// signal of a setting is the last value the last amplifier outputs.
+#[derive(Debug, Clone)]
+pub struct AmplifierChain {
+    program: Machine,
+    phases: Vec<i64>,
+    mode: Mode,
+    amplifiers: usize,
+    threads: usize,
+}
+
+impl AmplifierChain {
+    /// One amplifier per phase in `phases`, evaluated on the calling thread.
+    pub fn new(program: Vec<i64>, phases: &[i64], mode: Mode) -> Self {
+        Self {
+            program: Machine::new(program),
+            phases: phases.to_vec(),
+            mode,
+            amplifiers: phases.len(),
+            threads: 1,
+        }
+    }
+
+    /// Sets the number of amplifiers, each with a different phase. The
+    /// search fails with [`ChainError::Amplifiers`] if there are none, or
+    /// more than phases to choose from.
+    pub fn amplifiers(&mut self, amplifiers: usize) -> &mut Self {
+        self.amplifiers = amplifiers;
+        self
+    }
+
+    fn check(&self, amplifiers: usize) -> Result<(), ChainError> {
+        match amplifiers > 0 && amplifiers <= self.phases.len() {
+            true => Ok(()),
+            false => Err(ChainError::Amplifiers {
+                amplifiers,
+                phases: self.phases.len(),
+            }),
+        }
+    }
+
+    /// Spreads the evaluation of the permutations over `threads` threads.
+    pub fn threads(&mut self, threads: usize) -> &mut Self {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Every way to give the amplifiers different phases, in lexicographic
+    /// order of their positions in the phase set.
+    pub fn permutations(&self) -> Vec<Vec<i64>> {
+        fn extend(
+            chain: &AmplifierChain,
+            used: &mut Vec<bool>,
+            current: &mut Vec<i64>,
+            all: &mut Vec<Vec<i64>>,
+        ) {
+            if current.len() == chain.amplifiers {
+                all.push(current.clone());
+                return;
+            }
+            for (n, &phase) in chain.phases.iter().enumerate() {
+                if !used[n] {
+                    used[n] = true;
+                    current.push(phase);
+                    extend(chain, used, current, all);
+                    current.pop();
+                    used[n] = false;
+                }
+            }
+        }
+
+        let mut all = Vec::new();
+        let mut used = vec![false; self.phases.len()];
+        extend(self, &mut used, &mut Vec::new(), &mut all);
+        all
+    }
+
+    /// The signal out of the last amplifier for one phase setting, which
+    /// must give every amplifier a different phase from the phase set.
+    ///
+    /// A linear chain runs the amplifiers one after another, each until it
+    /// halts, while a feedback chain runs them together as a [`Network`].
+    pub fn signal(&self, phases: &[i64]) -> Result<i64, ChainError> {
+        self.check(phases.len())?;
+        let mut used = vec![false; self.phases.len()];
+        for &phase in phases {
+            let unused = (0..used.len()).find(|&n| !used[n] && self.phases[n] == phase);
+            match unused {
+                Some(n) => used[n] = true,
+                None => {
+                    return Err(ChainError::Phases {
+                        phases: phases.to_vec(),
+                    })
+                }
+            }
+        }
+        let signal = match self.mode {
+            Mode::Linear => self.linear(phases)?,
+            Mode::Feedback => self.feedback(phases)?,
+        };
+        signal.ok_or_else(|| ChainError::NoSignal {
+            phases: phases.to_vec(),
+        })
+    }
+
+    fn linear(&self, phases: &[i64]) -> Result<Option<i64>, ChainError> {
+        let mut signals = vec![0];
+        for (n, &phase) in phases.iter().enumerate() {
+            let mut amplifier = self.program.clone();
+            amplifier.push_input(phase);
+            for signal in signals.drain(..) {
+                amplifier.push_input(signal);
+            }
+            loop {
+                match amplifier.run_until_event() {
+                    Ok(Event::Output(signal)) => signals.push(signal),
+                    Ok(Event::Halted) => break,
+                    Ok(Event::NeedsInput) => {
+                        return Err(ChainError::NeedsInput {
+                            phases: phases.to_vec(),
+                            amplifier: n,
+                        })
+                    }
+                    Err(error) => {
+                        return Err(ChainError::Amplifier {
+                            phases: phases.to_vec(),
+                            amplifier: n,
+                            error,
+                        })
+                    }
+                }
+            }
+        }
+        Ok(signals.last().copied())
+    }
+
+    fn feedback(&self, phases: &[i64]) -> Result<Option<i64>, ChainError> {
+        let mut network = Network::new();
+        let amplifiers: Vec<_> = phases
+            .iter()
+            .map(|&phase| {
+                let mut amplifier = self.program.clone();
+                amplifier.push_input(phase);
+                network.add(amplifier)
+            })
+            .collect();
+        for pair in amplifiers.windows(2) {
+            network.pipe(pair[0], pair[1]);
+        }
+        let (first, last) = (amplifiers[0], amplifiers[amplifiers.len() - 1]);
+        network.push_input(first, 0).feedback(last, first);
+        let outputs = network.run().map_err(|error| ChainError::Network {
+            phases: phases.to_vec(),
+            error,
+        })?;
+        Ok(outputs[last].last().copied())
+    }
+
+    /// The phase setting with the highest signal, the first one in the
+    /// order of [`AmplifierChain::permutations`] on a tie.
+    pub fn best(&self) -> Result<Setting, ChainError> {
+        self.check(self.amplifiers)?;
+        let permutations = self.permutations();
+        let chunk = permutations.len().div_ceil(self.threads);
+        let search = |permutations: &[Vec<i64>]| -> Result<Option<Setting>, ChainError> {
+            let mut best: Option<Setting> = None;
+            for phases in permutations {
+                let signal = self.signal(phases)?;
+                if best.as_ref().is_none_or(|best| signal > best.signal) {
+                    best = Some(Setting {
+                        phases: phases.clone(),
+                        signal,
+                    });
+                }
+            }
+            Ok(best)
+        };
+        let results = match self.threads {
+            1 => vec![search(&permutations)],
+            _ => thread::scope(|scope| {
+                let workers: Vec<_> = permutations
+                    .chunks(chunk.max(1))
+                    .map(|chunk| scope.spawn(move || search(chunk)))
+                    .collect();
+                workers
+                    .into_iter()
+                    .map(|worker| worker.join().expect("amplifier thread panicked"))
+                    .collect()
+            }),
+        };
+        let mut best: Option<Setting> = None;
+        for setting in results {
+            if let Some(setting) = setting? {
+                if best
+                    .as_ref()
+                    .is_none_or(|best| setting.signal > best.signal)
+                {
+                    best = Some(setting);
+                }
+            }
+        }
+        Ok(best.expect("there is at least one permutation"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::ErrorKind, loader::parse_program};
+
+    use std::fs::read_to_string;
+
+    #[test]
+    fn test_examples() {
+        let best = |program: Vec<i64>, phases: &[i64], mode| {
+            let setting = AmplifierChain::new(program, phases, mode).best().unwrap();
+            (setting.phases, setting.signal)
+        };
+        assert_eq!(
+            best(
+                vec![3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0],
+                &[0, 1, 2, 3, 4],
+                Mode::Linear
+            ),
+            (vec![4, 3, 2, 1, 0], 43210)
+        );
+        assert_eq!(
+            best(
+                vec![
+                    3, 23, 3, 24, 1002, 24, 10, 24, 1002, 23, -1, 23, 101, 5, 23, 23, 1, 24, 23,
+                    23, 4, 23, 99, 0, 0
+                ],
+                &[0, 1, 2, 3, 4],
+                Mode::Linear
+            ),
+            (vec![0, 1, 2, 3, 4], 54321)
+        );
+        assert_eq!(
+            best(
+                vec![
+                    3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001,
+                    28, -1, 28, 1005, 28, 6, 99, 0, 0, 5
+                ],
+                &[5, 6, 7, 8, 9],
+                Mode::Feedback
+            ),
+            (vec![9, 8, 7, 6, 5], 139629729)
+        );
+    }
+
+    #[test]
+    fn test_chain() {
+        let program = parse_program(&read_to_string("../day_07/input.txt").unwrap()).unwrap();
+        let mut chain = AmplifierChain::new(program, &[5, 6, 7, 8, 9], Mode::Feedback);
+        assert_eq!(chain.permutations().len(), 120);
+        let best = chain.best().unwrap();
+        assert_eq!(chain.threads(4).best().unwrap(), best);
+
+        chain.amplifiers(3);
+        let permutations = chain.permutations();
+        assert_eq!(permutations.len(), 60);
+        assert_eq!(permutations[..2], [vec![5, 6, 7], vec![5, 6, 8]]);
+        let best = chain.best().unwrap();
+        assert_eq!(best.phases.len(), 3);
+        assert_eq!(chain.threads(3).best().unwrap(), best);
+    }
+
+    #[test]
+    fn test_errors() {
+        let chain = AmplifierChain::new(vec![3, 0, 99], &[0, 1], Mode::Linear);
+        assert_eq!(
+            chain.best(),
+            Err(ChainError::NoSignal { phases: vec![0, 1] })
+        );
+        let mut chain = AmplifierChain::new(vec![3, 0, 3, 0, 42], &[0, 1], Mode::Linear);
+        match chain.best() {
+            Err(ChainError::Amplifier {
+                phases,
+                amplifier,
+                error,
+            }) => assert_eq!(
+                (phases, amplifier, error.kind),
+                (vec![0, 1], 0, ErrorKind::UnknownOpcode)
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+        chain.mode = Mode::Feedback;
+        match chain.best() {
+            Err(ChainError::Network {
+                phases,
+                error: NetworkError::Machine { error, .. },
+            }) => assert_eq!((phases, error.kind), (vec![0, 1], ErrorKind::UnknownOpcode)),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // Reads a phase and two signals.
+        let chain = AmplifierChain::new(vec![3, 0, 3, 0, 3, 0, 99], &[0, 1], Mode::Linear);
+        assert_eq!(
+            chain.signal(&[1, 0]),
+            Err(ChainError::NeedsInput {
+                phases: vec![1, 0],
+                amplifier: 0
+            })
+        );
+
+        let mut chain = AmplifierChain::new(vec![99], &[0, 1], Mode::Linear);
+        assert_eq!(
+            chain.signal(&[]),
+            Err(ChainError::Amplifiers {
+                amplifiers: 0,
+                phases: 2
+            })
+        );
+        let error = chain.amplifiers(3).best().unwrap_err();
+        assert_eq!(error.to_string(), "cannot pick 3 of 2 phases");
+
+        for phases in [[0, 0], [0, 2]] {
+            assert_eq!(
+                chain.signal(&phases),
+                Err(ChainError::Phases {
+                    phases: phases.to_vec()
+                })
+            );
+        }
+        assert_eq!(
+            chain.signal(&[0, 0]).unwrap_err().to_string(),
+            "phases [0, 0]: not different phases from the phase set"
+        );
+        // A phase the set holds twice can be used twice.
+        let chain = AmplifierChain::new(vec![3, 0, 3, 0, 104, 7, 99], &[1, 1], Mode::Linear);
+        assert_eq!(chain.signal(&[1, 1]), Ok(7));
+    }
+}
@@ -0,0 +1,189 @@
+use crate::{
+    error::Error,
+    io::{Input, Output},
+    machine::{Machine, Status},
+    word::Word,
+};
+
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, Write},
+};
+
+/// Input read a line of text at a time, fed to the machine as one character
+/// code per value, each line terminated by 10.
+#[derive(Debug)]
+pub struct AsciiInput<R> {
+    reader: R,
+    /// The rest of the line being read.
+    pending: VecDeque<u8>,
+}
+
+impl<R: BufRead> AsciiInput<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            pending: VecDeque::new(),
+        }
+    }
+}
+
+impl<W: Word, R: BufRead> Input<W> for AsciiInput<R> {
+    fn read_value(&mut self) -> io::Result<Option<W>> {
+        if self.pending.is_empty() {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            if !line.is_ascii() {
+                let message = format!("not ASCII: {:?}", line.trim_end());
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            }
+            // Windows line endings end in 10 too.
+            let line = line.trim_end_matches(&['\r', '\n'][..]);
+            self.pending.extend(line.bytes().chain(Some(b'\n')));
+        }
+        Ok(self.pending.pop_front().map(i64::from).map(W::from_i64))
+    }
+}
+
+/// Output written as text for values in `0..=127`, while any other value is
+/// kept aside in `results`, since programs report their answer that way.
+#[derive(Debug)]
+pub struct AsciiOutput<T, W = i64> {
+    pub writer: T,
+    pub results: Vec<W>,
+}
+
+impl<T: Write, W> AsciiOutput<T, W> {
+    pub fn new(writer: T) -> Self {
+        Self {
+            writer,
+            results: Vec::new(),
+        }
+    }
+}
+
+impl<W: Word, T: Write> Output<W> for AsciiOutput<T, W> {
+    fn write_value(&mut self, value: W) -> io::Result<()> {
+        match value.to_i128() {
+            code @ 0..=127 => self.writer.write_all(&[code as u8]),
+            _ => {
+                self.results.push(value);
+                Ok(())
+            }
+        }
+    }
+}
+
+/// What a machine printed during [`run_script`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Transcript<W = i64> {
+    pub text: String,
+    /// The values that were not text, in the order they were output.
+    pub results: Vec<W>,
+    /// [`Status::Blocking`] if the script ran out before the machine halted.
+    pub status: Status,
+}
+
+/// Runs a machine without interaction, taking each line of `script` as a
+/// command. Lines starting with `#` are comments, and are skipped.
+pub fn run_script<W: Word>(machine: &mut Machine<W>, script: &str) -> Result<Transcript<W>, Error> {
+    let commands: String = script
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .flat_map(|line| vec![line, "\n"])
+        .collect();
+    let mut out = AsciiOutput::new(Vec::new());
+    let status = machine.run(&mut AsciiInput::new(commands.as_bytes()), &mut out)?;
+    Ok(Transcript {
+        text: String::from_utf8(out.writer).expect("ASCII is valid UTF-8"),
+        results: out.results,
+        status,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm::assemble, error::ErrorKind};
+
+    /// Echoes every line in upper case until it reads an empty one, then
+    /// outputs 1000 plus the number of lines it echoed as a result.
+    fn shout() -> Machine {
+        let program = assemble(
+            "
+            line:
+                in -> [c]
+                eq [c], #10 -> [t]
+                jnz [t], end
+                lt [c], #97 -> [t]
+                jnz [t], print
+                add [c], #-32 -> [c]
+            print:
+                add [c], #0 -> [last]
+                out [c]
+                jz #0, line
+            end:
+                eq [last], #10 -> [t]
+                jnz [t], done
+                add [n], #1 -> [n]
+                jz #0, print
+            done:
+                out [n]
+                hlt
+            c:    .data 0
+            t:    .data 0
+            n:    .data 1000
+            last: .data 10
+            ",
+        )
+        .unwrap();
+        Machine::new(program)
+    }
+
+    #[test]
+    fn test_adapters() {
+        let mut inn = AsciiInput::new("hi\r\nthere\n".as_bytes());
+        let mut read = || Input::<i64>::read_value(&mut inn).unwrap();
+        let values: Vec<_> = (0..10).map(|_| read()).collect();
+        let expected: Vec<_> = "hi\nthere\n".bytes().map(|b| Some(b as i64)).collect();
+        assert_eq!(values[..9], expected[..]);
+        assert_eq!(values[9], None);
+
+        let mut inn = AsciiInput::new("né\n".as_bytes());
+        assert_eq!(
+            Input::<i64>::read_value(&mut inn).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        let mut out = AsciiOutput::new(Vec::new());
+        for value in [72_i64, 105, 10, 128, -1, 19690720] {
+            out.write_value(value).unwrap();
+        }
+        assert_eq!(out.writer, b"Hi\n");
+        assert_eq!(out.results, vec![128, -1, 19690720]);
+    }
+
+    #[test]
+    fn test_script() {
+        let transcript = run_script(&mut shout(), "# greet\nhello\nworld\n\n").unwrap();
+        assert_eq!(
+            transcript,
+            Transcript {
+                text: "HELLO\nWORLD\n".to_string(),
+                results: vec![1002],
+                status: Status::Halted,
+            }
+        );
+
+        let mut machine = shout();
+        let transcript = run_script(&mut machine, "wait").unwrap();
+        assert_eq!(transcript.text, "WAIT\n");
+        assert_eq!(transcript.status, Status::Blocking);
+        let err = machine
+            .run_to_halt(&mut VecDeque::new(), &mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::InputExhausted);
+    }
+}
@@ -0,0 +1,549 @@
+// The impls generated by `err-derive` live inside an anonymous const.
+#![allow(non_local_definitions)]
+
+use crate::parameter::{Parameter, ParameterMode};
+
+use err_derive::Error;
+use std::{collections::HashMap, convert::TryFrom};
+
+/// How deep macros may expand other macros before giving up.
+const MAX_MACRO_DEPTH: usize = 32;
+
+/// A problem in assembly source, with the 1-based line it was found on.
+#[derive(Debug, Error, PartialEq, Eq, Clone)]
+#[error(display = "line {}: {}", line, message)]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+fn error<T>(line: usize, message: impl Into<String>) -> Result<T, AsmError> {
+    Err(AsmError {
+        line,
+        message: message.into(),
+    })
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    Number(i64),
+    Label(String, i64),
+}
+
+#[derive(Debug, Clone)]
+struct Operand {
+    mode: ParameterMode,
+    value: Value,
+}
+
+#[derive(Debug)]
+enum Statement {
+    Instruction(usize, Vec<Operand>),
+    Data(Vec<Value>),
+}
+
+#[derive(Debug)]
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+fn opcode(mnemonic: &str) -> Option<usize> {
+    match mnemonic.to_ascii_lowercase().as_str() {
+        "add" => Some(1),
+        "mul" => Some(2),
+        "in" => Some(3),
+        "out" => Some(4),
+        "jnz" => Some(5),
+        "jz" => Some(6),
+        "lt" => Some(7),
+        "eq" => Some(8),
+        "arb" => Some(9),
+        "hlt" => Some(99),
+        _ => None,
+    }
+}
+
+fn is_ident(text: &str) -> bool {
+    let mut chars = text.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '.' => {
+            chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+        }
+        _ => false,
+    }
+}
+
+fn parse_value(text: &str, line: usize) -> Result<Value, AsmError> {
+    let text = text.trim();
+    if let Ok(n) = text.parse() {
+        return Ok(Value::Number(n));
+    }
+    let (name, offset) = match text.find(['+', '-']) {
+        Some(split) => match text[split..].replace(' ', "").parse() {
+            Ok(offset) => (text[..split].trim(), offset),
+            Err(_) => return error(line, format!("invalid offset in `{}`", text)),
+        },
+        None => (text, 0),
+    };
+    match is_ident(name) {
+        true => Ok(Value::Label(name.to_string(), offset)),
+        false => error(line, format!("invalid value `{}`", text)),
+    }
+}
+
+fn parse_operand(text: &str, line: usize) -> Result<Operand, AsmError> {
+    let text = text.trim();
+    if let Some(value) = text.strip_prefix('#') {
+        return Ok(Operand {
+            mode: ParameterMode::ImmediateMode,
+            value: parse_value(value, line)?,
+        });
+    }
+    if let Some(inner) = text.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        let inner = inner.trim();
+        let relative = ["rb", "r"].iter().find_map(|base| {
+            inner
+                .strip_prefix(base)
+                .map(str::trim_start)
+                .filter(|rest| rest.is_empty() || rest.starts_with(['+', '-']))
+        });
+        return match relative {
+            Some("") => Ok(Operand {
+                mode: ParameterMode::RelativeMode,
+                value: Value::Number(0),
+            }),
+            Some(offset) => match offset.replace(' ', "").parse() {
+                Ok(n) => Ok(Operand {
+                    mode: ParameterMode::RelativeMode,
+                    value: Value::Number(n),
+                }),
+                Err(_) => error(line, format!("invalid relative offset in `{}`", text)),
+            },
+            None => Ok(Operand {
+                mode: ParameterMode::PositionMode,
+                value: parse_value(inner, line)?,
+            }),
+        };
+    }
+    Ok(Operand {
+        mode: ParameterMode::ImmediateMode,
+        value: parse_value(text, line)?,
+    })
+}
+
+fn split_list(text: &str) -> impl Iterator<Item = &str> {
+    text.split(',').map(str::trim).filter(|t| !t.is_empty())
+}
+
+/// Replaces `\param` with the arguments of one macro invocation and `\@`
+/// with a number unique to the invocation.
+fn expand(
+    name: &str,
+    args: &str,
+    line: usize,
+    macros: &HashMap<String, Macro>,
+    counter: &mut usize,
+    depth: usize,
+    out: &mut Vec<(usize, String)>,
+) -> Result<(), AsmError> {
+    if depth > MAX_MACRO_DEPTH {
+        return error(line, format!("macro `{}` expands too deeply", name));
+    }
+    let mac = &macros[name];
+    let args: Vec<_> = split_list(args).collect();
+    if args.len() != mac.params.len() {
+        return error(
+            line,
+            format!(
+                "macro `{}` takes {} arguments, got {}",
+                name,
+                mac.params.len(),
+                args.len()
+            ),
+        );
+    }
+    *counter += 1;
+    let unique = counter.to_string();
+    for body in &mac.body {
+        let text = substitute(body, &mac.params, &args, &unique);
+        let (_, rest) = split_labels(&text);
+        let word = rest.split_whitespace().next().unwrap_or("");
+        match macros.contains_key(word) {
+            true => {
+                let (labels, _) = split_labels(&text);
+                for label in labels {
+                    out.push((line, format!("{}:", label)));
+                }
+                let args = rest[word.len()..].to_string();
+                expand(word, &args, line, macros, counter, depth + 1, out)?;
+            }
+            false => out.push((line, text)),
+        }
+    }
+    Ok(())
+}
+
+/// Replaces every `\` followed by the longest parameter name it starts with
+/// by the matching argument, and `\@` by `unique`. This takes a single pass,
+/// so neither a parameter that is a prefix of another nor an argument
+/// containing `\` is substituted twice.
+fn substitute(body: &str, params: &[String], args: &[&str], unique: &str) -> String {
+    let mut text = String::new();
+    let mut rest = body;
+    while let Some(at) = rest.find('\\') {
+        text.push_str(&rest[..at]);
+        rest = &rest[at + 1..];
+        let (param, arg) = match rest.strip_prefix('@') {
+            Some(_) => ("@", unique),
+            None => params
+                .iter()
+                .zip(args)
+                .filter(|(param, _)| rest.starts_with(param.as_str()))
+                .max_by_key(|(param, _)| param.len())
+                .map_or(("", "\\"), |(param, &arg)| (param.as_str(), arg)),
+        };
+        text.push_str(arg);
+        rest = &rest[param.len()..];
+    }
+    text.push_str(rest);
+    text
+}
+
+/// Splits leading `label:` definitions from the rest of a line.
+fn split_labels(text: &str) -> (Vec<&str>, &str) {
+    let mut labels = Vec::new();
+    let mut rest = text.trim();
+    while let Some(colon) = rest.find(':') {
+        let label = rest[..colon].trim();
+        if label.is_empty() || label.contains(char::is_whitespace) {
+            break;
+        }
+        labels.push(label);
+        rest = rest[colon + 1..].trim();
+    }
+    (labels, rest)
+}
+
+/// Collects macro definitions and expands every invocation.
+fn preprocess(source: &str) -> Result<Vec<(usize, String)>, AsmError> {
+    let mut macros = HashMap::new();
+    let mut lines = Vec::new();
+    let mut current: Option<(usize, String, Macro)> = None;
+    let mut counter = 0;
+    for (n, text) in source.lines().enumerate() {
+        let line = n + 1;
+        let text = text.split(';').next().unwrap().trim();
+        let mut words = text.split_whitespace();
+        let first = words.next().unwrap_or("").to_ascii_lowercase();
+        match (first.as_str(), &mut current) {
+            (".macro", Some(_)) => return error(line, "macros can not be nested"),
+            (".macro", None) => {
+                let name = match words.next() {
+                    Some(name) if is_ident(name) => name.to_string(),
+                    _ => return error(line, "expected a macro name"),
+                };
+                if opcode(&name).is_some() {
+                    return error(line, format!("macro `{}` shadows an instruction", name));
+                }
+                let rest = text.splitn(3, char::is_whitespace).nth(2).unwrap_or("");
+                let params = split_list(rest).map(str::to_string).collect();
+                let body = Vec::new();
+                current = Some((line, name, Macro { params, body }));
+            }
+            (".endm", Some(_)) => {
+                let (_, name, mac) = current.take().unwrap();
+                macros.insert(name, mac);
+            }
+            (".endm", None) => return error(line, "`.endm` without `.macro`"),
+            (_, Some((_, _, mac))) => mac.body.push(text.to_string()),
+            _ => {
+                let (labels, rest) = split_labels(text);
+                let word = rest.split_whitespace().next().unwrap_or("");
+                match macros.contains_key(word) {
+                    true => {
+                        for label in labels {
+                            lines.push((line, format!("{}:", label)));
+                        }
+                        let args = &rest[word.len()..];
+                        expand(word, args, line, &macros, &mut counter, 0, &mut lines)?;
+                    }
+                    false => lines.push((line, text.to_string())),
+                }
+            }
+        }
+    }
+    match current {
+        Some((line, name, _)) => error(line, format!("macro `{}` is missing `.endm`", name)),
+        None => Ok(lines),
+    }
+}
+
+fn parse_statement(text: &str, line: usize) -> Result<Option<Statement>, AsmError> {
+    if text.is_empty() {
+        return Ok(None);
+    }
+    let word = text.split_whitespace().next().unwrap();
+    let rest = text[word.len()..].trim();
+    let lower = word.to_ascii_lowercase();
+    if lower == ".data" || lower == "data" {
+        let values = split_list(rest)
+            .map(|v| parse_value(v, line))
+            .collect::<Result<_, _>>()?;
+        return Ok(Some(Statement::Data(values)));
+    }
+    let opcode = match opcode(word) {
+        Some(opcode) => opcode,
+        None => return error(line, format!("unknown instruction `{}`", word)),
+    };
+    let probe = Parameter {
+        opcode,
+        a: ParameterMode::PositionMode,
+        b: ParameterMode::PositionMode,
+        c: ParameterMode::PositionMode,
+    };
+    let (sources, target) = match rest.find("->") {
+        Some(arrow) => (&rest[..arrow], Some(&rest[arrow + 2..])),
+        None => (rest, None),
+    };
+    let mut operands = split_list(sources)
+        .map(|o| parse_operand(o, line))
+        .collect::<Result<Vec<_>, _>>()?;
+    if let Some(target) = target {
+        if !probe.writes() {
+            return error(line, format!("`{}` does not write a result", word));
+        }
+        operands.push(parse_operand(target, line)?);
+    }
+    let count = probe.arg_count().unwrap();
+    if operands.len() != count {
+        return error(
+            line,
+            format!(
+                "`{}` takes {} operands, got {}",
+                word,
+                count,
+                operands.len()
+            ),
+        );
+    }
+    if probe.writes() && operands[count - 1].mode == ParameterMode::ImmediateMode {
+        return error(line, "can not write to an immediate operand");
+    }
+    Ok(Some(Statement::Instruction(opcode, operands)))
+}
+
+fn resolve(value: &Value, labels: &HashMap<String, usize>, line: usize) -> Result<i64, AsmError> {
+    match value {
+        Value::Number(n) => Ok(*n),
+        Value::Label(name, offset) => match labels.get(name) {
+            Some(&address) => match i64::try_from(address)
+                .ok()
+                .and_then(|address| address.checked_add(*offset))
+            {
+                Some(value) => Ok(value),
+                None => error(line, format!("`{}{:+}` overflows", name, offset)),
+            },
+            None => error(line, format!("unknown label `{}`", name)),
+        },
+    }
+}
+
+/// Assembles mnemonic source into a program image.
+///
+/// Operands are written `#imm`, `[pos]` or `[rb+n]`, and a bare label is an
+/// immediate holding the label's address. The written operand may be split
+/// off with `->`. `.data` lays out raw values and `.macro name params` ...
+/// `.endm` defines a macro, whose body refers to its parameters as `\param`
+/// and to a per expansion unique number as `\@`. A label made of digits only
+/// asserts the current address, so disassembler listings assemble as-is.
+pub fn assemble(source: &str) -> Result<Vec<i64>, AsmError> {
+    let mut labels = HashMap::new();
+    let mut statements = Vec::new();
+    let mut address = 0;
+    for (line, text) in preprocess(source)? {
+        let (names, rest) = split_labels(&text);
+        for name in names {
+            if let Ok(expected) = name.parse::<usize>() {
+                if expected != address {
+                    return error(
+                        line,
+                        format!("expected address {}, but is at {}", expected, address),
+                    );
+                }
+            } else if !is_ident(name) {
+                return error(line, format!("invalid label `{}`", name));
+            } else if labels.insert(name.to_string(), address).is_some() {
+                return error(line, format!("label `{}` is defined twice", name));
+            }
+        }
+        if let Some(statement) = parse_statement(rest, line)? {
+            address += match &statement {
+                Statement::Instruction(_, operands) => operands.len() + 1,
+                Statement::Data(values) => values.len(),
+            };
+            statements.push((line, statement));
+        }
+    }
+
+    let mut program = Vec::with_capacity(address);
+    for (line, statement) in statements {
+        match statement {
+            Statement::Data(values) => {
+                for value in &values {
+                    program.push(resolve(value, &labels, line)?);
+                }
+            }
+            Statement::Instruction(opcode, operands) => {
+                let mode = |n: usize| {
+                    operands
+                        .get(n)
+                        .map_or(ParameterMode::PositionMode, |o| o.mode)
+                };
+                let param = Parameter {
+                    opcode,
+                    c: mode(0),
+                    b: mode(1),
+                    a: mode(2),
+                };
+                program.push(param.encode());
+                for operand in &operands {
+                    program.push(resolve(&operand.value, &labels, line)?);
+                }
+            }
+        }
+    }
+    Ok(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{disasm::disassemble, loader::parse_program};
+
+    use std::fs::read_to_string;
+
+    #[test]
+    fn test_instructions() {
+        let program = assemble(
+            "
+            in -> [12]          ; read a value
+            jnz [12], nonzero
+            out #0
+            hlt
+            .data 7
+        nonzero:
+            out #1
+            hlt
+            .data 0
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            program,
+            vec![3, 12, 1005, 12, 9, 104, 0, 99, 7, 104, 1, 99, 0]
+        );
+    }
+
+    #[test]
+    fn test_operands() {
+        assert_eq!(
+            assemble("add #3, [rb-2], [r+4]\nARB #-3\nmul [a], #b+1 -> [rb]\na: b: hlt").unwrap(),
+            vec![22101, 3, -2, 4, 109, -3, 21002, 10, 11, 0, 99]
+        );
+    }
+
+    #[test]
+    fn test_macros() {
+        let source = "
+            .macro push value
+                add \\value, #0 -> [rb]
+                arb #1
+            .endm
+            .macro countdown from
+                add #\\from, #0 -> [counter]
+            loop\\@:
+                push [counter]
+                add [counter], #-1 -> [counter]
+                jnz [counter], loop\\@
+            .endm
+                arb #stack
+                countdown 2
+                countdown 1
+                hlt
+            counter: .data 0
+            stack:
+        ";
+        let program = assemble(source).unwrap();
+        let mut machine = crate::Machine::new(program.clone());
+        machine
+            .run_to_halt(&mut std::collections::VecDeque::new(), &mut Vec::new())
+            .unwrap();
+        let stack = program.len();
+        assert_eq!(machine.relative_base() as usize, stack + 3);
+        assert_eq!(machine.memory()[stack], 2);
+        assert_eq!(machine.memory()[stack + 1], 1);
+        assert_eq!(machine.memory()[stack + 2], 1);
+    }
+
+    #[test]
+    fn test_macro_params() {
+        let source = "
+            .macro pair x, xy
+                out #\\xy
+                out #\\x
+            .endm
+                pair 1, 2
+                hlt
+        ";
+        assert_eq!(assemble(source).unwrap(), vec![104, 2, 104, 1, 99]);
+        let params = ["a".to_string(), "b".to_string()];
+        assert_eq!(
+            substitute("\\a \\b\\@ \\c", &params, &["\\b", "[x]"], "7"),
+            "\\b [x]7 \\c"
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let line = |source| assemble(source).unwrap_err().line;
+        assert_eq!(line("hlt\nfoo #1"), 2);
+        assert_eq!(line("add #1, #2"), 1);
+        assert_eq!(line("add #1, #2 -> #3"), 1);
+        assert_eq!(line("out -> [1]"), 1);
+        assert_eq!(line("jz #0, nowhere"), 1);
+        assert_eq!(line("a: hlt\na: hlt"), 2);
+        assert_eq!(line("hlt\n0000: hlt"), 2);
+        assert_eq!(line(".macro m x\nhlt"), 1);
+        assert_eq!(line(".macro m\nm\n.endm\nm"), 4);
+        assert_eq!(
+            assemble("out [x]").unwrap_err().to_string(),
+            "line 1: unknown label `x`"
+        );
+        assert_eq!(
+            assemble("add #1, #1 -> [x+9223372036854775807]\nx: .data 0")
+                .unwrap_err()
+                .to_string(),
+            "line 1: `x+9223372036854775807` overflows"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let samples = [
+            "../day_02/input.txt",
+            "../day_05/input.txt",
+            "../day_07/input.txt",
+        ];
+        for path in samples.iter() {
+            let program = parse_program(&read_to_string(path).unwrap()).unwrap();
+            let listing = disassemble(&program).to_string();
+            assert_eq!(assemble(&listing).unwrap(), program, "{}", path);
+        }
+        let program = vec![3, 12, 1005, 12, 9, 104, 0, 99, 7, 104, 1, 99, 0, 21101, -4];
+        assert_eq!(
+            assemble(&disassemble(&program).to_string()).unwrap(),
+            program
+        );
+    }
+}
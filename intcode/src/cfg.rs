@@ -0,0 +1,451 @@
+use crate::{
+    disasm::{format_instruction, label_name},
+    parameter::{Parameter, ParameterMode},
+};
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    fmt::{self, Write},
+};
+
+/// The word at `address`, reading zero past the end like a machine does.
+pub(crate) fn word(program: &[i64], address: usize) -> i64 {
+    program.get(address).copied().unwrap_or(0)
+}
+
+/// An instruction reachable from the entry point.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Instruction {
+    pub address: usize,
+    pub param: Parameter,
+    pub args: Vec<i64>,
+}
+
+impl Instruction {
+    /// Decodes the instruction at `address`, `None` if the opcode is unknown.
+    fn decode(program: &[i64], address: usize) -> Option<Self> {
+        let param = Parameter::try_from(word(program, address)).ok()?;
+        let count = param.arg_count()?;
+        Some(Self {
+            address,
+            param,
+            args: (1..=count).map(|n| word(program, address + n)).collect(),
+        })
+    }
+
+    /// How many memory cells the instruction covers.
+    pub fn len(&self) -> usize {
+        self.args.len() + 1
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The mode and value of argument `n`.
+    pub fn arg(&self, n: usize) -> (ParameterMode, i64) {
+        (self.param.modes()[n], self.args[n])
+    }
+
+    /// Whether a conditional jump always, never, or only sometimes jumps.
+    pub fn jumps(&self) -> Option<bool> {
+        match (self.param.opcode, self.arg(0)) {
+            (5, (ParameterMode::ImmediateMode, value)) => Some(value != 0),
+            (6, (ParameterMode::ImmediateMode, value)) => Some(value == 0),
+            _ => None,
+        }
+    }
+
+    /// Whether this is a jump that is not always skipped.
+    fn may_jump(&self) -> bool {
+        matches!(self.param.opcode, 5 | 6) && self.jumps() != Some(false)
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut words = vec![self.param.encode()];
+        words.extend(&self.args);
+        match format_instruction(&words) {
+            Some(text) => write!(f, "{:04}: {}", self.address, text),
+            None => write!(f, "{:04}: {:?}", self.address, words),
+        }
+    }
+}
+
+/// Where a jump goes.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Target {
+    Address(usize),
+    /// The target is read from memory.
+    Computed,
+    /// A negative target, which stops the machine with an error.
+    Negative(i64),
+}
+
+/// How control leaves a basic block.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Exit {
+    /// Runs into the block starting at the address.
+    Fallthrough(usize),
+    /// Ends in a jump, which falls through to `next` unless it always jumps.
+    Jump {
+        target: Target,
+        next: Option<usize>,
+    },
+    Halt,
+}
+
+/// A run of instructions that is only ever entered at its first one.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Block {
+    pub instructions: Vec<Instruction>,
+    pub exit: Exit,
+}
+
+impl Block {
+    pub fn start(&self) -> usize {
+        self.instructions[0].address
+    }
+
+    /// The address just past the last instruction.
+    pub fn end(&self) -> usize {
+        let last = self.instructions.last().unwrap();
+        last.address + last.len()
+    }
+
+    /// The blocks control can continue with, jump target first.
+    pub fn successors(&self) -> Vec<usize> {
+        match self.exit {
+            Exit::Fallthrough(next) => vec![next],
+            Exit::Jump { target, next } => match target {
+                Target::Address(target) => Some(target).into_iter().chain(next).collect(),
+                _ => next.into_iter().collect(),
+            },
+            Exit::Halt => Vec::new(),
+        }
+    }
+}
+
+/// The control-flow graph of the code reachable from its entry points.
+///
+/// Both edges of every conditional jump with an immediate target are
+/// followed, while jumps whose condition is an immediate only take the edge
+/// they always take. Jumps to an address read from memory cannot be
+/// followed, and are left as [`Target::Computed`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Cfg {
+    pub blocks: BTreeMap<usize, Block>,
+    /// Reachable addresses that do not hold a known instruction.
+    pub invalid: BTreeSet<usize>,
+}
+
+impl Cfg {
+    /// The graph of the code reachable from address 0.
+    pub fn new(program: &[i64]) -> Self {
+        Self::with_entries(program, &[0])
+    }
+
+    /// The graph of the code reachable from any of `entries`.
+    ///
+    /// Extra entries let the analysis continue past what it cannot follow,
+    /// like the targets of a jump table, or code the program patches before
+    /// running it.
+    pub fn with_entries(program: &[i64], entries: &[usize]) -> Self {
+        let mut instructions = BTreeMap::new();
+        let mut invalid = BTreeSet::new();
+        let mut leaders: BTreeSet<_> = entries.iter().copied().collect();
+        let mut reached = BTreeMap::new();
+        let mut work = entries.to_vec();
+        while let Some(address) = work.pop() {
+            *reached.entry(address).or_insert(0) += 1;
+            if instructions.contains_key(&address) || invalid.contains(&address) {
+                continue;
+            }
+            let instruction = match Instruction::decode(program, address) {
+                Some(instruction) => instruction,
+                None => {
+                    invalid.insert(address);
+                    continue;
+                }
+            };
+            let next = address + instruction.len();
+            match instruction.param.opcode {
+                99 => (),
+                5 | 6 => {
+                    if let (true, (ParameterMode::ImmediateMode, target)) =
+                        (instruction.may_jump(), instruction.arg(1))
+                    {
+                        if let Ok(target) = usize::try_from(target) {
+                            leaders.insert(target);
+                            work.push(target);
+                        }
+                    }
+                    if instruction.jumps() != Some(true) {
+                        if instruction.may_jump() {
+                            leaders.insert(next);
+                        }
+                        work.push(next);
+                    }
+                }
+                _ => work.push(next),
+            }
+            instructions.insert(address, instruction);
+        }
+        // Code reached from more than one place starts a block too.
+        leaders.extend(
+            reached
+                .into_iter()
+                .filter(|&(_, count)| count > 1)
+                .map(|(address, _)| address),
+        );
+
+        let mut blocks = BTreeMap::new();
+        for &leader in &leaders {
+            let mut block = Vec::new();
+            let mut address = leader;
+            let exit = loop {
+                let instruction = match instructions.get(&address) {
+                    Some(instruction) => instruction.clone(),
+                    None => break Exit::Fallthrough(address),
+                };
+                address += instruction.len();
+                let exit = match instruction.param.opcode {
+                    99 => Some(Exit::Halt),
+                    5 | 6 if instruction.may_jump() => Some(Exit::Jump {
+                        target: match instruction.arg(1) {
+                            (ParameterMode::ImmediateMode, target) => match usize::try_from(target)
+                            {
+                                Ok(target) => Target::Address(target),
+                                Err(_) => Target::Negative(target),
+                            },
+                            _ => Target::Computed,
+                        },
+                        next: match instruction.jumps() {
+                            Some(true) => None,
+                            _ => Some(address),
+                        },
+                    }),
+                    _ => None,
+                };
+                block.push(instruction);
+                if let Some(exit) = exit {
+                    break exit;
+                }
+                if leaders.contains(&address) {
+                    break Exit::Fallthrough(address);
+                }
+            };
+            // Invalid leaders have no instructions, so no block.
+            if !block.is_empty() {
+                blocks.insert(
+                    leader,
+                    Block {
+                        instructions: block,
+                        exit,
+                    },
+                );
+            }
+        }
+        Self { blocks, invalid }
+    }
+
+    /// Every reachable instruction, in address order.
+    pub fn instructions(&self) -> impl Iterator<Item = &Instruction> {
+        self.blocks.values().flat_map(|block| &block.instructions)
+    }
+
+    /// Renders the graph in the Graphviz DOT language.
+    ///
+    /// Blocks list their instructions, conditional edges are labelled with
+    /// whether the jump is taken, and halts, computed jumps and faults lead
+    /// to nodes of their own.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        self.write_dot(&mut dot).unwrap();
+        dot
+    }
+
+    fn write_dot(&self, f: &mut dyn Write) -> fmt::Result {
+        writeln!(f, "digraph cfg {{")?;
+        writeln!(f, "    node [shape=box, fontname=monospace];")?;
+        for (&start, block) in &self.blocks {
+            write!(
+                f,
+                "    {} [label=\"{}:\\l",
+                label_name(start),
+                label_name(start)
+            )?;
+            for instruction in &block.instructions {
+                write!(f, "{}\\l", instruction)?;
+            }
+            writeln!(f, "\"];")?;
+        }
+        for &address in &self.invalid {
+            writeln!(
+                f,
+                "    {} [label=\"{:04}: not an instruction\", color=red];",
+                label_name(address),
+                address
+            )?;
+        }
+        let mut sinks = BTreeSet::new();
+        for (&start, block) in &self.blocks {
+            let from = label_name(start);
+            match block.exit {
+                Exit::Fallthrough(next) => writeln!(f, "    {} -> {};", from, label_name(next))?,
+                Exit::Halt => {
+                    writeln!(f, "    {} -> halt;", from)?;
+                    sinks.insert("    halt [shape=doublecircle];");
+                }
+                Exit::Jump { target, next } => {
+                    let label = match next {
+                        Some(next) => {
+                            writeln!(
+                                f,
+                                "    {} -> {} [label=\"not taken\"];",
+                                from,
+                                label_name(next)
+                            )?;
+                            " [label=\"taken\"]"
+                        }
+                        None => "",
+                    };
+                    let to = match target {
+                        Target::Address(target) => label_name(target),
+                        Target::Computed => {
+                            sinks.insert("    computed [label=\"computed jump\", shape=diamond];");
+                            "computed".to_string()
+                        }
+                        Target::Negative(_) => {
+                            sinks.insert("    fault [shape=octagon, color=red];");
+                            "fault".to_string()
+                        }
+                    };
+                    writeln!(f, "    {} -> {}{};", from, to, label)?;
+                }
+            }
+        }
+        for sink in sinks {
+            writeln!(f, "{}", sink)?;
+        }
+        writeln!(f, "}}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm::assemble, loader::parse_program};
+
+    use std::fs::read_to_string;
+
+    #[test]
+    fn test_blocks() {
+        let program = assemble(
+            "
+                in -> [x]
+            loop:
+                jz [x], end
+                add [x], #-1 -> [x]
+                out [x]
+                jnz #1, loop
+            end:
+                jnz #1, [x]
+            x:  .data 0
+            ",
+        )
+        .unwrap();
+        let cfg = Cfg::new(&program);
+        let exits: Vec<_> = cfg
+            .blocks
+            .iter()
+            .map(|(&start, block)| (start, block.end(), block.exit))
+            .collect();
+        assert_eq!(
+            exits,
+            vec![
+                (0, 2, Exit::Fallthrough(2)),
+                (
+                    2,
+                    5,
+                    Exit::Jump {
+                        target: Target::Address(14),
+                        next: Some(5)
+                    }
+                ),
+                (
+                    5,
+                    14,
+                    Exit::Jump {
+                        target: Target::Address(2),
+                        next: None
+                    }
+                ),
+                (
+                    14,
+                    17,
+                    Exit::Jump {
+                        target: Target::Computed,
+                        next: None
+                    }
+                ),
+            ]
+        );
+        assert_eq!(cfg.blocks[&2].successors(), vec![14, 5]);
+        assert_eq!(cfg.instructions().count(), 6);
+        assert!(cfg.invalid.is_empty());
+    }
+
+    #[test]
+    fn test_dot() {
+        let cfg = Cfg::new(&[1005, 7, 6, 104, 1, 99, 55, 0]);
+        assert_eq!(
+            cfg.to_dot(),
+            r#"digraph cfg {
+    node [shape=box, fontname=monospace];
+    L0000 [label="L0000:\l0000: JNZ [7], #6\l"];
+    L0003 [label="L0003:\l0003: OUT #1\l0005: HLT\l"];
+    L0006 [label="0006: not an instruction", color=red];
+    L0000 -> L0003 [label="not taken"];
+    L0000 -> L0006 [label="taken"];
+    L0003 -> halt;
+    halt [shape=doublecircle];
+}
+"#
+        );
+    }
+
+    #[test]
+    fn test_puzzle_inputs() {
+        let program = parse_program(&read_to_string("../day_05/input.txt").unwrap()).unwrap();
+        // The first instructions patch the one at 6 into an addition for
+        // the first part, and into a jump to 238 for the second.
+        assert_eq!(Cfg::new(&program).invalid, vec![6].into_iter().collect());
+        let cfg = Cfg::with_entries(&program, &[0, 10, 238]);
+        assert_eq!(cfg.blocks[&10].exit, Exit::Halt);
+        assert!(cfg.blocks.len() > 10);
+        assert!(cfg.invalid.contains(&6));
+
+        // Dispatches on the phase setting through a jump table, which
+        // starts with the entry point.
+        let program = parse_program(&read_to_string("../day_07/input.txt").unwrap()).unwrap();
+        let table: Vec<_> = program[9..19].iter().map(|&a| a as usize).collect();
+        let cfg = Cfg::with_entries(&program, &table);
+        assert_eq!(cfg.blocks.len(), 10);
+        assert!(cfg.invalid.is_empty());
+        for (&start, block) in &cfg.blocks {
+            match start {
+                0 => assert_eq!(
+                    block.exit,
+                    Exit::Jump {
+                        target: Target::Computed,
+                        next: None
+                    }
+                ),
+                _ => assert_eq!(block.exit, Exit::Halt),
+            }
+        }
+    }
+}
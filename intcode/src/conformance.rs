@@ -0,0 +1,365 @@
+use crate::{
+    error::Error,
+    loader::{parse_program, ParseError},
+    machine::Machine,
+    memory::Memory,
+    threaded::ThreadedMachine,
+};
+
+use std::{collections::VecDeque, fmt};
+
+/// A program together with how it is expected to run.
+///
+/// Cases are written as `key: value` lines, each case starting with its
+/// name:
+///
+/// ```text
+/// # Lines starting with # are comments.
+/// case: add in position mode
+/// program: 1,5,6,7,99,20,30,0
+/// inputs:
+/// outputs:
+/// memory: 7=50
+/// ```
+///
+/// `inputs` and `outputs` are comma separated values, and both default to
+/// none. `memory` lists the cells to check, as `address=value`. `error` is
+/// the message of the [`ErrorKind`](crate::ErrorKind) the program stops
+/// with, if it does not halt. A case without a program runs the one of the
+/// case before it.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Case {
+    pub name: String,
+    pub program: Vec<i64>,
+    pub inputs: Vec<i64>,
+    pub outputs: Vec<i64>,
+    pub memory: Vec<(usize, i64)>,
+    pub error: Option<String>,
+}
+
+/// A case that a backend ran differently.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Failure {
+    pub case: String,
+    /// Either `machine` or `threaded`.
+    pub backend: &'static str,
+    pub message: String,
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({}): {}", self.case, self.backend, self.message)
+    }
+}
+
+impl std::error::Error for Failure {}
+
+/// Parses the text form of a list of cases.
+pub fn parse_cases(text: &str) -> Result<Vec<Case>, ParseError> {
+    let mut cases: Vec<Case> = Vec::new();
+    // Where the last case starts.
+    let mut case_line = 0;
+    let no_program = |case: &Case, line: usize| ParseError {
+        line,
+        column: 1,
+        token: case.name.clone(),
+        message: "no program for case".to_string(),
+    };
+    for (n, line) in text.lines().enumerate() {
+        let error = |column: usize, token: &str, message: &str| ParseError {
+            line: n + 1,
+            column: column + 1,
+            token: token.to_string(),
+            message: message.to_string(),
+        };
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let colon = line
+            .find(':')
+            .ok_or_else(|| error(0, line.trim(), "expected `key: value`, found"))?;
+        let (key, value) = (line[..colon].trim(), line[colon + 1..].trim());
+        // Where `value` starts on the line, for errors within it.
+        let start = line.len() - line[colon + 1..].trim_start().len();
+        let values = || {
+            parse_program(value)
+                .map_err(|err| error(start + err.column - 1, &err.token, &err.message))
+        };
+        if key == "case" {
+            if let Some(case) = cases.last().filter(|case| case.program.is_empty()) {
+                return Err(no_program(case, case_line));
+            }
+            case_line = n + 1;
+            cases.push(Case {
+                name: value.to_string(),
+                program: cases.last().map_or(Vec::new(), |case| case.program.clone()),
+                inputs: Vec::new(),
+                outputs: Vec::new(),
+                memory: Vec::new(),
+                error: None,
+            });
+            continue;
+        }
+        let case = cases
+            .last_mut()
+            .ok_or_else(|| error(0, key, "expected `case:` before"))?;
+        match key {
+            "program" => case.program = values()?,
+            "inputs" => case.inputs = values()?,
+            "outputs" => case.outputs = values()?,
+            "memory" => {
+                let mut at = start;
+                for cell in value.split(',') {
+                    let token = cell.trim();
+                    let column = at + cell.len() - cell.trim_start().len();
+                    at += cell.len() + 1;
+                    if token.is_empty() {
+                        continue;
+                    }
+                    let parsed = token.split_once('=').and_then(|(address, value)| {
+                        Some((address.trim().parse().ok()?, value.trim().parse().ok()?))
+                    });
+                    let cell = parsed
+                        .ok_or_else(|| error(column, token, "expected `address=value`, found"))?;
+                    case.memory.push(cell);
+                }
+            }
+            "error" => case.error = Some(value.to_string()),
+            _ => return Err(error(0, key, "unknown key")),
+        }
+    }
+    match cases.last() {
+        Some(case) if case.program.is_empty() => Err(no_program(case, case_line)),
+        _ => Ok(cases),
+    }
+}
+
+impl Case {
+    /// Runs the case on both [`Machine`] and [`ThreadedMachine`].
+    pub fn check(&self) -> Result<(), Failure> {
+        let mut inn: VecDeque<_> = self.inputs.iter().copied().collect();
+        let mut out = Vec::new();
+        let mut machine = Machine::new(self.program.clone());
+        let result = machine.run_to_halt(&mut inn.clone(), &mut out);
+        self.compare("machine", result, out, machine.memory())?;
+
+        let mut out = Vec::new();
+        let mut machine = ThreadedMachine::from(Machine::new(self.program.clone()));
+        let result = machine.run_to_halt(&mut inn, &mut out);
+        self.compare("threaded", result, out, machine.memory())
+    }
+
+    fn compare(
+        &self,
+        backend: &'static str,
+        result: Result<(), Error>,
+        outputs: Vec<i64>,
+        memory: &Memory,
+    ) -> Result<(), Failure> {
+        let fail = |message: String| {
+            Err(Failure {
+                case: self.name.clone(),
+                backend,
+                message,
+            })
+        };
+        let error = result.err().map(|err| err.kind.to_string());
+        if error != self.error {
+            return match (&self.error, error) {
+                (Some(expected), Some(actual)) => {
+                    fail(format!("expected error `{}`, got `{}`", expected, actual))
+                }
+                (Some(expected), None) => {
+                    fail(format!("expected error `{}`, but halted", expected))
+                }
+                (_, Some(actual)) => fail(format!("unexpected error `{}`", actual)),
+                (None, None) => unreachable!(),
+            };
+        }
+        if outputs != self.outputs {
+            return fail(format!(
+                "expected outputs {:?}, got {:?}",
+                self.outputs, outputs
+            ));
+        }
+        for &(address, expected) in &self.memory {
+            if memory[address] != expected {
+                return fail(format!(
+                    "expected {} at address {}, found {}",
+                    expected, address, memory[address]
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parameter::ParameterMode, trace::Traced};
+
+    use std::{fs, path::Path};
+
+    fn suite() -> Vec<Case> {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/conformance");
+        let mut paths: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+            .collect();
+        paths.sort();
+        paths
+            .iter()
+            .flat_map(|path| {
+                let text = fs::read_to_string(path).unwrap();
+                parse_cases(&text).unwrap_or_else(|err| panic!("{}: {}", path.display(), err))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let text = "
+            # day 2
+            case: first
+            program: 1,0,0,0,99
+            memory: 0=2, 4=99
+
+            case: second
+            inputs: 7, -1
+            outputs: 3
+            error: unknown opcode
+        ";
+        let first = Case {
+            name: "first".into(),
+            program: vec![1, 0, 0, 0, 99],
+            inputs: vec![],
+            outputs: vec![],
+            memory: vec![(0, 2), (4, 99)],
+            error: None,
+        };
+        let second = Case {
+            name: "second".into(),
+            inputs: vec![7, -1],
+            outputs: vec![3],
+            memory: vec![],
+            error: Some("unknown opcode".into()),
+            ..first.clone()
+        };
+        assert_eq!(parse_cases(text).unwrap(), vec![first, second]);
+
+        let error = |text| {
+            let err = parse_cases(text).unwrap_err();
+            (err.line, err.column, err.token, err.message)
+        };
+        assert_eq!(
+            error("case: a\nprogram: 1,x,99"),
+            (2, 12, "x".into(), "expected a number, found".into())
+        );
+        assert_eq!(
+            error("case: a\nprogram: 99\nmemory: 0=99, 1"),
+            (3, 15, "1".into(), "expected `address=value`, found".into())
+        );
+        assert_eq!(
+            error("program: 99"),
+            (1, 1, "program".into(), "expected `case:` before".into())
+        );
+        assert_eq!(
+            error("case: a\ncase: b\nprogram: 99"),
+            (1, 1, "a".into(), "no program for case".into())
+        );
+        assert_eq!(
+            error("case: a\nprogram: 99\noutput: 1"),
+            (3, 1, "output".into(), "unknown key".into())
+        );
+    }
+
+    #[test]
+    fn test_check() {
+        let case = parse_cases("case: add\nprogram: 1,5,6,7,99,20,30,0\nmemory: 7=50")
+            .unwrap()
+            .remove(0);
+        assert_eq!(case.check(), Ok(()));
+        let wrong = Case {
+            memory: vec![(7, 60)],
+            ..case.clone()
+        };
+        assert_eq!(
+            wrong.check().unwrap_err().to_string(),
+            "add (machine): expected 60 at address 7, found 50"
+        );
+        let wrong = Case {
+            error: Some("unknown opcode".into()),
+            ..case
+        };
+        assert_eq!(
+            wrong.check().unwrap_err().message,
+            "expected error `unknown opcode`, but halted"
+        );
+    }
+
+    #[test]
+    fn test_suite() {
+        let failures: Vec<_> = suite()
+            .iter()
+            .filter_map(|case| case.check().err())
+            .map(|failure| failure.to_string())
+            .collect();
+        assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+    }
+
+    /// Every opcode runs in the suite with every combination of modes its
+    /// arguments allow.
+    #[test]
+    fn test_coverage() {
+        use ParameterMode::*;
+
+        let mut executed = Vec::new();
+        for case in suite() {
+            let mut machine = Machine::new(case.program);
+            let mut inn: VecDeque<_> = case.inputs.into();
+            while let Ok((_, Some(entry))) = machine.step_traced(&mut inn, &mut Vec::new()) {
+                executed.push((entry.opcode, entry.modes));
+            }
+        }
+        let shapes = [
+            (1, 3),
+            (2, 3),
+            (3, 1),
+            (4, 1),
+            (5, 2),
+            (6, 2),
+            (7, 3),
+            (8, 3),
+            (9, 1),
+        ];
+        let mut missing = Vec::new();
+        for &(opcode, count) in &shapes {
+            let mut combinations = vec![vec![]];
+            for n in 0..count {
+                let writes = n == count - 1 && matches!(opcode, 1 | 2 | 3 | 7 | 8);
+                let modes: &[_] = match writes {
+                    true => &[PositionMode, RelativeMode],
+                    false => &[PositionMode, ImmediateMode, RelativeMode],
+                };
+                combinations = combinations
+                    .iter()
+                    .flat_map(|prefix: &Vec<_>| {
+                        modes.iter().map(move |&mode| {
+                            let mut modes = prefix.clone();
+                            modes.push(mode);
+                            modes
+                        })
+                    })
+                    .collect();
+            }
+            for modes in combinations {
+                if !executed.contains(&(opcode, modes.clone())) {
+                    missing.push(format!("{} {:?}", opcode, modes));
+                }
+            }
+        }
+        assert!(missing.is_empty(), "\n{}", missing.join("\n"));
+    }
+}
@@ -0,0 +1,372 @@
+use crate::{
+    disasm::format_instruction,
+    machine::{Machine, Status},
+    parameter::Parameter,
+};
+
+use std::{
+    collections::{BTreeSet, VecDeque},
+    convert::TryFrom,
+    io::{self, BufRead, Write},
+};
+
+const HELP: &str = "\
+commands:
+    s, step [n]             run n instructions (default 1)
+    c, continue             run until a breakpoint, watchpoint, halt or missing input
+    b, break <addr>         stop before running the instruction at addr
+    w, watch <addr>         stop after an instruction writes to addr
+    d, delete <addr>        remove breakpoints and watchpoints at addr
+    p, print <addr>[..<addr>]
+                            show memory at addr, or the half open range of at most 1024
+    r, regs                 show ip, relative base and status
+    set <addr> <value>      write value to addr
+    i, input <value>...     queue values for the program to read
+    h, help                 show this help
+    q, quit                 leave the debugger
+An empty line repeats the last command.";
+
+/// How many addresses `print` shows at most.
+const MAX_PRINT: usize = 1024;
+
+/// Why the machine stopped running.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Stop {
+    Stepped,
+    Breakpoint(usize),
+    Watchpoint { address: usize, ip: usize },
+    Blocking,
+    Halted,
+}
+
+/// An interactive debugger wrapping a [`Machine`].
+#[derive(Debug)]
+pub struct Debugger {
+    machine: Machine,
+    breakpoints: BTreeSet<usize>,
+    watchpoints: BTreeSet<usize>,
+    input: VecDeque<i64>,
+    last_command: String,
+}
+
+fn parse<T: std::str::FromStr>(arg: Option<&str>, what: &str) -> Result<T, String> {
+    match arg {
+        Some(arg) => arg
+            .parse()
+            .map_err(|_| format!("invalid {}: {}", what, arg)),
+        None => Err(format!("missing {}", what)),
+    }
+}
+
+impl Debugger {
+    pub fn new(machine: Machine) -> Self {
+        Self {
+            machine,
+            breakpoints: BTreeSet::new(),
+            watchpoints: BTreeSet::new(),
+            input: VecDeque::new(),
+            last_command: String::new(),
+        }
+    }
+
+    pub fn machine(&self) -> &Machine {
+        &self.machine
+    }
+
+    /// The instruction at `ip` as a listing line.
+    fn current_instruction(&self) -> String {
+        let ip = self.machine.ip();
+        let words: Vec<_> = (0..4)
+            .filter_map(|n| ip.checked_add(n))
+            .map(|a| self.machine.memory()[a])
+            .collect();
+        let text = format_instruction(&words).unwrap_or_else(|| format!("DATA {}", words[0]));
+        format!("{:04}: {}", ip, text)
+    }
+
+    /// Runs one instruction, printing any output it produces.
+    fn step_one(&mut self, out: &mut dyn Write) -> Result<Stop, String> {
+        let ip = self.machine.ip();
+        let written = Parameter::try_from(self.machine.memory()[ip])
+            .ok()
+            .and_then(|param| {
+                param.write_address(ip, self.machine.memory(), self.machine.relative_base())
+            });
+        let mut output = Vec::new();
+        let status = self
+            .machine
+            .step(&mut self.input, &mut output)
+            .map_err(|err| err.to_string())?;
+        for value in output {
+            writeln!(out, "output: {}", value).map_err(|err| err.to_string())?;
+        }
+        Ok(match (status, written) {
+            (Status::Halted, _) => Stop::Halted,
+            (Status::Blocking, _) => Stop::Blocking,
+            (_, Some(address)) if self.watchpoints.contains(&address) => {
+                Stop::Watchpoint { address, ip }
+            }
+            _ => Stop::Stepped,
+        })
+    }
+
+    /// Runs until something other than a plain step stops the machine.
+    fn run(&mut self, out: &mut dyn Write, mut steps: Option<usize>) -> Result<Stop, String> {
+        let mut first = true;
+        loop {
+            let ip = self.machine.ip();
+            if !first && self.breakpoints.contains(&ip) {
+                return Ok(Stop::Breakpoint(ip));
+            }
+            first = false;
+            match self.step_one(out)? {
+                Stop::Stepped => (),
+                stop => return Ok(stop),
+            }
+            if let Some(n) = steps.as_mut() {
+                *n -= 1;
+                if *n == 0 {
+                    return Ok(Stop::Stepped);
+                }
+            }
+        }
+    }
+
+    fn report(&self, stop: Stop, out: &mut dyn Write) -> io::Result<()> {
+        match stop {
+            Stop::Stepped => (),
+            Stop::Breakpoint(ip) => writeln!(out, "breakpoint at {:04}", ip)?,
+            Stop::Watchpoint { address, ip } => {
+                writeln!(out, "watchpoint {:04} written by {:04}", address, ip)?
+            }
+            Stop::Blocking => writeln!(out, "waiting for input")?,
+            Stop::Halted => return writeln!(out, "halted"),
+        }
+        writeln!(out, "{}", self.current_instruction())
+    }
+
+    fn command(&mut self, line: &str, out: &mut dyn Write) -> Result<bool, String> {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let io = |err: io::Error| err.to_string();
+        match command {
+            "s" | "step" => {
+                let n = match words.next() {
+                    Some(arg) => parse(Some(arg), "count")?,
+                    None => 1,
+                };
+                if n > 0 {
+                    let stop = self.run(out, Some(n))?;
+                    self.report(stop, out).map_err(io)?;
+                }
+            }
+            "c" | "continue" => {
+                let stop = self.run(out, None)?;
+                self.report(stop, out).map_err(io)?;
+            }
+            "b" | "break" => {
+                self.breakpoints.insert(parse(words.next(), "address")?);
+            }
+            "w" | "watch" => {
+                self.watchpoints.insert(parse(words.next(), "address")?);
+            }
+            "d" | "delete" => {
+                let address = parse(words.next(), "address")?;
+                self.breakpoints.remove(&address);
+                self.watchpoints.remove(&address);
+            }
+            "p" | "print" => {
+                let arg = words.next();
+                let (start, count) = match arg.and_then(|a| a.find("..").map(|n| a.split_at(n))) {
+                    Some((start, end)) => {
+                        let start: usize = parse(Some(start), "address")?;
+                        let end: usize = parse(Some(&end[2..]), "address")?;
+                        (start, end.saturating_sub(start))
+                    }
+                    None => (parse(arg, "address")?, 1),
+                };
+                if count > MAX_PRINT {
+                    return Err(format!("at most {} addresses can be printed", MAX_PRINT));
+                }
+                for address in (0..count).map(|n| start + n) {
+                    writeln!(out, "{:04}: {}", address, self.machine.memory()[address])
+                        .map_err(io)?;
+                }
+            }
+            "r" | "regs" => {
+                writeln!(
+                    out,
+                    "ip: {}\nrelative base: {}\nstatus: {:?}",
+                    self.machine.ip(),
+                    self.machine.relative_base(),
+                    self.machine.status()
+                )
+                .map_err(io)?;
+                writeln!(out, "{}", self.current_instruction()).map_err(io)?;
+            }
+            "set" => {
+                let address = parse(words.next(), "address")?;
+                let value = parse(words.next(), "value")?;
+                self.machine
+                    .memory_mut()
+                    .set(address, value)
+                    .map_err(|err| err.to_string())?;
+            }
+            "i" | "input" => {
+                for word in words {
+                    self.input.push_back(parse(Some(word), "value")?);
+                }
+            }
+            "h" | "help" => writeln!(out, "{}", HELP).map_err(io)?,
+            "q" | "quit" => return Ok(false),
+            "" => (),
+            _ => return Err(format!("unknown command: {} (try `help`)", command)),
+        }
+        Ok(true)
+    }
+
+    /// Runs a single debugger command, returning `false` when asked to quit.
+    pub fn execute(&mut self, line: &str, out: &mut dyn Write) -> io::Result<bool> {
+        let line = match line.trim() {
+            "" => self.last_command.clone(),
+            line => line.to_string(),
+        };
+        self.last_command = line.clone();
+        match self.command(&line, out) {
+            Ok(running) => Ok(running),
+            Err(message) => {
+                writeln!(out, "error: {}", message)?;
+                Ok(true)
+            }
+        }
+    }
+
+    /// Reads commands from `inn` until it is exhausted or `quit` is given.
+    pub fn repl<R: BufRead, W: Write>(&mut self, inn: R, mut out: W) -> io::Result<()> {
+        writeln!(out, "{}", self.current_instruction())?;
+        write!(out, "> ")?;
+        out.flush()?;
+        for line in inn.lines() {
+            if !self.execute(&line?, &mut out)? {
+                break;
+            }
+            write!(out, "> ")?;
+            out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::assemble;
+
+    fn debugger() -> Debugger {
+        let program = assemble(
+            "
+                in -> [x]
+            loop:
+                add [x], #-1 -> [x]
+                out [x]
+                jnz [x], loop
+                hlt
+            x:  .data 0
+            ",
+        )
+        .unwrap();
+        Debugger::new(Machine::new(program))
+    }
+
+    fn session(debugger: &mut Debugger, commands: &[&str]) -> String {
+        let mut out = Vec::new();
+        for command in commands {
+            debugger.execute(command, &mut out).unwrap();
+        }
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_step_and_input() {
+        let mut debugger = debugger();
+        assert_eq!(
+            session(&mut debugger, &["step"]),
+            "waiting for input\n0000: IN -> [12]\n"
+        );
+        assert_eq!(
+            session(&mut debugger, &["input 2", "s", ""]),
+            "0002: ADD [12], #-1 -> [12]\n0006: OUT [12]\n"
+        );
+        assert_eq!(debugger.machine().memory()[12], 1);
+        assert_eq!(
+            session(&mut debugger, &["regs"]),
+            "ip: 6\nrelative base: 0\nstatus: Running\n0006: OUT [12]\n"
+        );
+    }
+
+    #[test]
+    fn test_breakpoints_and_watchpoints() {
+        let mut debugger = debugger();
+        assert_eq!(
+            session(&mut debugger, &["input 3", "break 8", "c"]),
+            "output: 2\nbreakpoint at 0008\n0008: JNZ [12], #2\n"
+        );
+        assert_eq!(
+            session(&mut debugger, &["delete 8", "watch 12", "c"]),
+            "watchpoint 0012 written by 0002\n0006: OUT [12]\n"
+        );
+        assert_eq!(
+            session(&mut debugger, &["d 12", "continue"]),
+            "output: 1\noutput: 0\nhalted\n"
+        );
+    }
+
+    #[test]
+    fn test_memory_commands() {
+        let mut debugger = debugger();
+        assert_eq!(
+            session(&mut debugger, &["set 12 5", "print 11..13", "p 12"]),
+            "0011: 99\n0012: 5\n0012: 5\n"
+        );
+        assert_eq!(
+            session(&mut debugger, &["print x", "frobnicate"]),
+            "error: invalid address: x\nerror: unknown command: frobnicate (try `help`)\n"
+        );
+        assert!(!debugger.execute("quit", &mut Vec::new()).unwrap());
+        assert_eq!(
+            session(&mut debugger, &["p 0..1025", "p 18446744073709551615"]),
+            "error: at most 1024 addresses can be printed\n18446744073709551615: 0\n"
+        );
+    }
+
+    #[test]
+    fn test_huge_ip() {
+        let mut debugger = debugger();
+        let mut snapshot = debugger.machine().snapshot();
+        snapshot.ip = usize::MAX - 1;
+        debugger.machine.restore(&snapshot);
+        assert_eq!(
+            session(&mut debugger, &["regs"]),
+            format!(
+                "ip: {}\nrelative base: 0\nstatus: NotYetStarted\n{:04}: DATA 0\n",
+                usize::MAX - 1,
+                usize::MAX - 1
+            )
+        );
+        let output = session(&mut debugger, &["step"]);
+        assert!(output.starts_with("error: address"), "{}", output);
+    }
+
+    #[test]
+    fn test_repl() {
+        let mut debugger = debugger();
+        let mut out = Vec::new();
+        debugger
+            .repl("i 1\nc\nq\ns\n".as_bytes(), &mut out)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "0000: IN -> [12]\n> > output: 0\nhalted\n> "
+        );
+    }
+}
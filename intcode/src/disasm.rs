@@ -0,0 +1,369 @@
+use crate::parameter::{Parameter, ParameterMode};
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    fmt,
+};
+
+/// Unreachable cells are grouped into `DATA` lines of at most this many values.
+const DATA_PER_LINE: usize = 8;
+
+/// One line of a disassembled program.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Line {
+    Instruction {
+        address: usize,
+        param: Parameter,
+        args: Vec<i64>,
+    },
+    Data {
+        address: usize,
+        values: Vec<i64>,
+    },
+}
+
+impl Line {
+    pub fn address(&self) -> usize {
+        match self {
+            Line::Instruction { address, .. } | Line::Data { address, .. } => *address,
+        }
+    }
+
+    /// How many memory cells the line covers.
+    pub fn len(&self) -> usize {
+        match self {
+            Line::Instruction { args, .. } => args.len() + 1,
+            Line::Data { values, .. } => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A disassembled program, rendered as an annotated listing by `Display`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Listing {
+    pub lines: Vec<Line>,
+    /// Jump targets that start an instruction.
+    pub labels: BTreeSet<usize>,
+}
+
+impl Listing {
+    /// The line covering `address`.
+    pub fn line_at(&self, address: usize) -> Option<&Line> {
+        let index = match self
+            .lines
+            .binary_search_by_key(&address, |line| line.address())
+        {
+            Ok(index) => index,
+            Err(0) => return None,
+            Err(index) => index - 1,
+        };
+        let line = &self.lines[index];
+        match address < line.address() + line.len() {
+            true => Some(line),
+            false => None,
+        }
+    }
+
+    /// Renders a single line without its address or label.
+    pub fn fmt_line(&self, line: &Line, f: &mut dyn fmt::Write) -> fmt::Result {
+        match line {
+            Line::Data { values, .. } => {
+                write!(f, "DATA ")?;
+                for (n, value) in values.iter().enumerate() {
+                    if n != 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                Ok(())
+            }
+            // Not something `disassemble` produces, such as a line built by
+            // hand with an unknown opcode, so its words are shown as data.
+            Line::Instruction { param, args, .. }
+                if param.mnemonic().is_none() || param.arg_count() != Some(args.len()) =>
+            {
+                // Opcodes past two digits do not fit in an instruction word.
+                match param.opcode < 100 {
+                    true => write!(f, "DATA {}", param.encode())?,
+                    false => write!(f, "DATA {}", param.opcode)?,
+                }
+                for value in args {
+                    write!(f, ", {}", value)?;
+                }
+                Ok(())
+            }
+            Line::Instruction { param, args, .. } => {
+                write!(f, "{}", param.mnemonic().unwrap())?;
+                let (sources, target) = match param.writes() {
+                    true => args.split_at(args.len() - 1),
+                    false => (&args[..], &[][..]),
+                };
+                for (n, (&value, mode)) in sources.iter().zip(param.modes().iter()).enumerate() {
+                    write!(f, "{}", if n == 0 { " " } else { ", " })?;
+                    let is_jump = matches!(param.opcode, 5 | 6) && n == 1;
+                    match usize::try_from(value) {
+                        Ok(label)
+                            if is_jump
+                                && *mode == ParameterMode::ImmediateMode
+                                && self.labels.contains(&label) =>
+                        {
+                            write!(f, "{}", label_name(label))?
+                        }
+                        _ => fmt_operand(*mode, value, f)?,
+                    }
+                }
+                if let Some(&value) = target.first() {
+                    write!(f, " -> ")?;
+                    fmt_operand(param.modes()[args.len() - 1], value, f)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Renders the instruction starting at `words[0]`, `None` if it does not decode.
+pub fn format_instruction(words: &[i64]) -> Option<String> {
+    let (param, len) = decode(words, 0)?;
+    let listing = Listing {
+        lines: Vec::new(),
+        labels: BTreeSet::new(),
+    };
+    let line = Line::Instruction {
+        address: 0,
+        param,
+        args: words[1..len].to_vec(),
+    };
+    let mut text = String::new();
+    listing.fmt_line(&line, &mut text).ok()?;
+    Some(text)
+}
+
+/// The name a jump target is given in a listing.
+pub fn label_name(address: usize) -> String {
+    format!("L{:04}", address)
+}
+
+fn fmt_operand(mode: ParameterMode, value: i64, f: &mut dyn fmt::Write) -> fmt::Result {
+    match mode {
+        ParameterMode::PositionMode => write!(f, "[{}]", value),
+        ParameterMode::ImmediateMode => write!(f, "#{}", value),
+        ParameterMode::RelativeMode if value < 0 => write!(f, "[r{}]", value),
+        ParameterMode::RelativeMode => write!(f, "[r+{}]", value),
+    }
+}
+
+impl fmt::Display for Listing {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for line in &self.lines {
+            if self.labels.contains(&line.address()) {
+                writeln!(f, "{}:", label_name(line.address()))?;
+            }
+            write!(f, "{:04}: ", line.address())?;
+            self.fmt_line(line, f)?;
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes the instruction at `address` if it is known and fits in the program.
+///
+/// Words with stray mode digits are rejected, so that every listed
+/// instruction assembles back to the exact same word.
+fn decode(program: &[i64], address: usize) -> Option<(Parameter, usize)> {
+    let word = *program.get(address)?;
+    let param = Parameter::try_from(word).ok()?;
+    let count = param.arg_count()?;
+    let canonical = param.encode() == word
+        && param.modes()[count.min(3)..]
+            .iter()
+            .all(|mode| *mode == ParameterMode::PositionMode);
+    match canonical && address + count < program.len() {
+        true => Some((param, count + 1)),
+        false => None,
+    }
+}
+
+/// Disassembles the code reachable from address 0.
+///
+/// Instructions are followed from the entry point, through both edges of
+/// every conditional jump with an immediate target. Cells that are never
+/// reached this way are listed as `DATA`.
+pub fn disassemble(program: &[i64]) -> Listing {
+    let mut covered = vec![false; program.len()];
+    let mut code = BTreeMap::new();
+    let mut targets = BTreeSet::new();
+    let mut work = vec![0];
+    while let Some(mut address) = work.pop() {
+        while address < program.len() && !covered[address] {
+            let (param, len) = match decode(program, address) {
+                Some(n) => n,
+                None => break,
+            };
+            if covered[address..address + len].iter().any(|&c| c) {
+                break;
+            }
+            covered[address..address + len]
+                .iter_mut()
+                .for_each(|c| *c = true);
+            code.insert(address, param);
+            match param.opcode {
+                99 => break,
+                5 | 6 => {
+                    let jumps_on_nonzero = param.opcode == 5;
+                    let condition = match param.c {
+                        ParameterMode::ImmediateMode => Some(program[address + 1] != 0),
+                        _ => None,
+                    };
+                    if param.b == ParameterMode::ImmediateMode
+                        && condition != Some(!jumps_on_nonzero)
+                    {
+                        if let Ok(target) = usize::try_from(program[address + 2]) {
+                            targets.insert(target);
+                            work.push(target);
+                        }
+                    }
+                    if condition == Some(jumps_on_nonzero) {
+                        break;
+                    }
+                }
+                _ => (),
+            }
+            address += len;
+        }
+    }
+
+    let mut lines = Vec::new();
+    let mut address = 0;
+    while address < program.len() {
+        match code.get(&address) {
+            Some(&param) => {
+                let len = param.arg_count().unwrap() + 1;
+                lines.push(Line::Instruction {
+                    address,
+                    param,
+                    args: program[address + 1..address + len].to_vec(),
+                });
+                address += len;
+            }
+            None => {
+                let end = (address..program.len())
+                    .take(DATA_PER_LINE)
+                    .find(|a| covered[*a])
+                    .unwrap_or_else(|| program.len().min(address + DATA_PER_LINE));
+                lines.push(Line::Data {
+                    address,
+                    values: program[address..end].to_vec(),
+                });
+                address = end;
+            }
+        }
+    }
+    let labels = targets
+        .into_iter()
+        .filter(|target| code.contains_key(target))
+        .collect();
+    Listing { lines, labels }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_operands() {
+        let listing = disassemble(&[21101, 3, 5, 104, 99]);
+        assert_eq!(
+            listing.to_string(),
+            "0000: ADD #3, #5 -> [r+104]\n0004: HLT\n"
+        );
+        let listing = disassemble(&[109, -3, 203, -1, 99]);
+        assert_eq!(
+            listing.to_string(),
+            "0000: ARB #-3\n0002: IN -> [r-1]\n0004: HLT\n"
+        );
+    }
+
+    #[test]
+    fn test_labels_and_data() {
+        let program = [3, 12, 1005, 12, 9, 104, 0, 99, 7, 104, 1, 99, 0];
+        let listing = disassemble(&program);
+        assert_eq!(
+            listing.to_string(),
+            "\
+0000: IN -> [12]
+0002: JNZ [12], L0009
+0005: OUT #0
+0007: HLT
+0008: DATA 7
+L0009:
+0009: OUT #1
+0011: HLT
+0012: DATA 0
+"
+        );
+        assert_eq!(listing.labels, vec![9].into_iter().collect());
+        assert_eq!(listing.line_at(10).unwrap().address(), 9);
+        assert_eq!(listing.line_at(8).unwrap().len(), 1);
+        assert!(listing.line_at(13).is_none());
+    }
+
+    #[test]
+    fn test_unconditional_jump() {
+        let program = [1105, 1, 4, 42, 99];
+        let listing = disassemble(&program);
+        assert_eq!(
+            listing.to_string(),
+            "0000: JNZ #1, L0004\n0003: DATA 42\nL0004:\n0004: HLT\n"
+        );
+    }
+
+    #[test]
+    fn test_format_instruction() {
+        assert_eq!(
+            format_instruction(&[1005, 3, 9, 42]).unwrap(),
+            "JNZ [3], #9"
+        );
+        assert_eq!(format_instruction(&[1005, 3]), None);
+        assert_eq!(format_instruction(&[]), None);
+    }
+
+    #[test]
+    fn test_unknown_and_truncated() {
+        let listing = disassemble(&[1, 0, 0]);
+        assert_eq!(listing.to_string(), "0000: DATA 1, 0, 0\n");
+        let listing = disassemble(&[3]);
+        assert_eq!(listing.to_string(), "0000: DATA 3\n");
+        let listing = disassemble(&[10099]);
+        assert_eq!(listing.to_string(), "0000: DATA 10099\n");
+
+        let line = |opcode, args: Vec<i64>| {
+            let listing = Listing {
+                lines: vec![Line::Instruction {
+                    address: 0,
+                    param: Parameter {
+                        opcode,
+                        a: ParameterMode::PositionMode,
+                        b: ParameterMode::PositionMode,
+                        c: ParameterMode::ImmediateMode,
+                    },
+                    args,
+                }],
+                labels: BTreeSet::new(),
+            };
+            listing.to_string()
+        };
+        assert_eq!(line(42, vec![7]), "0000: DATA 142, 7\n");
+        assert_eq!(
+            line(usize::MAX, vec![]),
+            "0000: DATA 18446744073709551615\n"
+        );
+        assert_eq!(line(1, vec![7]), "0000: DATA 101, 7\n");
+    }
+}
@@ -0,0 +1,79 @@
+// The impls generated by `err-derive` live inside an anonymous const.
+#![allow(non_local_definitions)]
+
+use crate::memory::MemoryLimitExceeded;
+
+use err_derive::Error;
+use std::io;
+
+/// What went wrong while running an instruction.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+pub enum ErrorKind {
+    #[error(display = "unknown opcode")]
+    UnknownOpcode,
+    #[error(display = "unknown parameter mode {} (0, 1 and 2 is valid)", _0)]
+    InvalidParameterMode(usize),
+    #[error(display = "negative address {}", _0)]
+    NegativeAddress(i128),
+    /// An address too large for `usize`, so beyond any memory limit.
+    #[error(display = "address {} is out of range", _0)]
+    AddressOutOfRange(i128),
+    #[error(display = "write to a parameter in immediate mode")]
+    WriteInImmediateMode,
+    #[error(display = "no more input available")]
+    InputExhausted,
+    #[error(display = "arithmetic overflow")]
+    ArithmeticOverflow,
+    #[error(
+        display = "address {} is outside of the memory limit of {}",
+        address,
+        limit
+    )]
+    MemoryLimit { address: usize, limit: usize },
+    #[error(display = "I/O error: {:?}", _0)]
+    Io(io::ErrorKind),
+}
+
+impl From<MemoryLimitExceeded> for ErrorKind {
+    fn from(err: MemoryLimitExceeded) -> Self {
+        ErrorKind::MemoryLimit {
+            address: err.address,
+            limit: err.limit,
+        }
+    }
+}
+
+impl From<io::Error> for ErrorKind {
+    fn from(err: io::Error) -> Self {
+        ErrorKind::Io(err.kind())
+    }
+}
+
+/// A failed instruction, together with where it failed.
+#[derive(Debug, Error, PartialEq, Eq, Clone, Copy)]
+#[error(display = "{} (ip: {}, instruction: {})", kind, ip, instruction)]
+pub struct Error {
+    /// Address of the faulting instruction.
+    pub ip: usize,
+    /// The raw instruction word at `ip`, widened to fit any [`Word`](crate::Word).
+    pub instruction: i128,
+    pub kind: ErrorKind,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let err = Error {
+            ip: 12,
+            instruction: 1107,
+            kind: ErrorKind::WriteInImmediateMode,
+        };
+        assert_eq!(
+            err.to_string(),
+            "write to a parameter in immediate mode (ip: 12, instruction: 1107)"
+        );
+    }
+}
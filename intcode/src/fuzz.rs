@@ -0,0 +1,390 @@
+use crate::{
+    error::ErrorKind,
+    machine::{Machine, Status},
+    parameter::{Parameter, ParameterMode},
+    threaded::ThreadedMachine,
+    word::Word,
+};
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    fmt,
+    num::Wrapping,
+};
+
+/// Cells after the halt instruction, which arguments point into as data.
+const DATA: usize = 8;
+
+/// Runs `program` on `inputs` for at most `budget` instructions.
+pub type Interpreter = fn(program: &[i64], inputs: &[i64], budget: usize) -> Outcome;
+
+/// Everything a run is compared on.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Outcome {
+    /// The cells that are not zero, widened to fit any [`Word`].
+    pub memory: BTreeMap<usize, i128>,
+    pub outputs: Vec<i128>,
+    pub error: Option<ErrorKind>,
+    /// [`Status::Running`] if the budget ran out first.
+    pub status: Status,
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.error {
+            Some(kind) => write!(f, "failed with {}", kind)?,
+            None => write!(f, "{:?}", self.status)?,
+        }
+        write!(f, ", outputs {:?}, memory {{", self.outputs)?;
+        for (n, (address, value)) in self.memory.iter().enumerate() {
+            let separator = if n == 0 { "" } else { ", " };
+            write!(f, "{}{}: {}", separator, address, value)?;
+        }
+        write!(f, "}}")
+    }
+}
+
+fn run<W: Word>(program: &[i64], inputs: &[i64], budget: usize, threaded: bool) -> Outcome {
+    let machine = Machine::from(program.iter().copied().map(W::from_i64).collect::<Vec<_>>());
+    let mut inn: VecDeque<W> = inputs.iter().copied().map(W::from_i64).collect();
+    let mut out = Vec::new();
+    let (status, error, memory) = match threaded {
+        true => {
+            let mut machine = ThreadedMachine::from(machine);
+            let result = (0..budget)
+                .map(|_| machine.step(&mut inn, &mut out))
+                .find(|result| !matches!(result, Ok(Status::Running)));
+            (
+                machine.status(),
+                result,
+                machine.into_machine().memory().clone(),
+            )
+        }
+        false => {
+            let mut machine = machine;
+            let result = (0..budget)
+                .map(|_| machine.step(&mut inn, &mut out))
+                .find(|result| !matches!(result, Ok(Status::Running)));
+            (machine.status(), result, machine.memory().clone())
+        }
+    };
+    Outcome {
+        memory: memory
+            .iter()
+            .filter(|&(_, value)| value != *W::ZERO)
+            .map(|(address, value)| (address, value.to_i128()))
+            .collect(),
+        outputs: out.into_iter().map(W::to_i128).collect(),
+        error: error.and_then(Result::err).map(|err| err.kind),
+        status,
+    }
+}
+
+/// The interpreters [`Fuzzer::new`] compares: [`Machine`] and
+/// [`ThreadedMachine`] on `i64`, and [`Machine`] on `i128` and
+/// `Wrapping<i64>`.
+pub fn interpreters() -> Vec<(&'static str, Interpreter)> {
+    vec![
+        ("machine", |program, inputs, budget| {
+            run::<i64>(program, inputs, budget, false)
+        }),
+        ("threaded", |program, inputs, budget| {
+            run::<i64>(program, inputs, budget, true)
+        }),
+        ("i128", |program, inputs, budget| {
+            run::<i128>(program, inputs, budget, false)
+        }),
+        ("wrapping", |program, inputs, budget| {
+            run::<Wrapping<i64>>(program, inputs, budget, false)
+        }),
+    ]
+}
+
+/// A program on which the interpreters do not agree.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Disagreement {
+    pub program: Vec<i64>,
+    pub inputs: Vec<i64>,
+    /// The outcome of every interpreter, by name.
+    pub outcomes: Vec<(&'static str, Outcome)>,
+}
+
+impl fmt::Display for Disagreement {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let join = |values: &[i64]| {
+            let text: Vec<_> = values.iter().map(i64::to_string).collect();
+            text.join(",")
+        };
+        writeln!(f, "program: {}", join(&self.program))?;
+        writeln!(f, "inputs:  {}", join(&self.inputs))?;
+        for (name, outcome) in &self.outcomes {
+            writeln!(f, "{:>9}: {}", name, outcome)?;
+        }
+        Ok(())
+    }
+}
+
+/// A splitmix64 generator, which is all the randomness the fuzzer needs.
+#[derive(Debug, Clone)]
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number in `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// A number in `low..=high`.
+    fn between(&mut self, low: i64, high: i64) -> i64 {
+        low + self.below((high - low + 1) as usize) as i64
+    }
+}
+
+/// Compares interpreters on random programs.
+///
+/// The programs stick to what every Intcode interpreter understands:
+/// opcodes 1 to 8 and 99, in position and immediate mode. They are well
+/// formed, in that arguments point into the program or are small
+/// immediates, jumps in immediate mode land on an instruction and nothing
+/// is written in immediate mode, but they do overwrite their own code at
+/// times, and whatever they run into once that happens is run as well.
+///
+/// Programs that overflow an `i64` are skipped, since the word types are
+/// meant to differ there.
+#[derive(Debug, Clone)]
+pub struct Fuzzer {
+    rng: Rng,
+    instructions: usize,
+    inputs: usize,
+    budget: usize,
+    interpreters: Vec<(&'static str, Interpreter)>,
+}
+
+impl Fuzzer {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng(seed),
+            instructions: 8,
+            inputs: 4,
+            budget: 1000,
+            interpreters: interpreters(),
+        }
+    }
+
+    /// How many instructions a program has before it halts.
+    pub fn instructions(&mut self, instructions: usize) -> &mut Self {
+        self.instructions = instructions;
+        self
+    }
+
+    /// How many inputs every program is given.
+    pub fn inputs(&mut self, inputs: usize) -> &mut Self {
+        self.inputs = inputs;
+        self
+    }
+
+    /// How many instructions every interpreter runs at most.
+    pub fn budget(&mut self, budget: usize) -> &mut Self {
+        self.budget = budget;
+        self
+    }
+
+    /// Adds an interpreter to compare with the others.
+    pub fn interpreter(&mut self, name: &'static str, interpreter: Interpreter) -> &mut Self {
+        self.interpreters.push((name, interpreter));
+        self
+    }
+
+    /// Runs up to `programs` random programs, returning the first
+    /// disagreement, minimized.
+    pub fn run(&mut self, programs: usize) -> Option<Disagreement> {
+        (0..programs).find_map(|_| {
+            let (program, inputs) = self.generate();
+            self.check(&program, &inputs)?;
+            let (program, inputs) = self.minimize(program, inputs);
+            self.check(&program, &inputs)
+        })
+    }
+
+    /// Runs every interpreter on the program, `None` if they agree.
+    pub fn check(&self, program: &[i64], inputs: &[i64]) -> Option<Disagreement> {
+        let outcomes: Vec<_> = self
+            .interpreters
+            .iter()
+            .map(|&(name, interpreter)| (name, interpreter(program, inputs, self.budget)))
+            .collect();
+        let overflows = outcomes
+            .iter()
+            .any(|(_, outcome)| outcome.error == Some(ErrorKind::ArithmeticOverflow));
+        let agree = outcomes.windows(2).all(|pair| pair[0].1 == pair[1].1);
+        match overflows || agree {
+            true => None,
+            false => Some(Disagreement {
+                program: program.to_vec(),
+                inputs: inputs.to_vec(),
+                outcomes,
+            }),
+        }
+    }
+
+    fn generate(&mut self) -> (Vec<i64>, Vec<i64>) {
+        let rng = &mut self.rng;
+        let params: Vec<_> = (0..self.instructions)
+            .map(|_| {
+                let mut modes = [ParameterMode::PositionMode; 3];
+                for mode in &mut modes {
+                    if rng.below(2) == 1 {
+                        *mode = ParameterMode::ImmediateMode;
+                    }
+                }
+                let opcode = rng.below(8) + 1;
+                let mut param = Parameter {
+                    opcode,
+                    c: modes[0],
+                    b: modes[1],
+                    a: modes[2],
+                };
+                if param.writes() {
+                    match param.arg_count() {
+                        Some(1) => param.c = ParameterMode::PositionMode,
+                        _ => param.a = ParameterMode::PositionMode,
+                    }
+                }
+                param
+            })
+            .collect();
+        let mut starts = Vec::new();
+        let mut code = 0;
+        for param in &params {
+            starts.push(code as i64);
+            code += 1 + param.arg_count().unwrap();
+        }
+        let len = code + 1 + DATA;
+
+        let mut program = Vec::with_capacity(len);
+        for param in &params {
+            program.push(param.encode());
+            let count = param.arg_count().unwrap();
+            for (n, mode) in param.modes()[..count].iter().enumerate() {
+                let jump = matches!(param.opcode, 5 | 6) && n == 1;
+                program.push(match mode {
+                    ParameterMode::ImmediateMode if jump => starts[rng.below(starts.len())],
+                    ParameterMode::ImmediateMode => rng.between(-10, 10),
+                    // Mostly data, so that programs get somewhere before
+                    // they overwrite their code.
+                    _ => match rng.below(4) {
+                        0 => rng.below(len) as i64,
+                        _ => (code + 1 + rng.below(DATA)) as i64,
+                    },
+                });
+            }
+        }
+        program.push(99);
+        program.extend((0..DATA).map(|_| rng.between(-10, 10)));
+        let inputs = (0..self.inputs).map(|_| rng.between(-10, 10)).collect();
+        (program, inputs)
+    }
+
+    /// Shrinks a disagreement greedily: drops inputs and words, and brings
+    /// values closer to zero, for as long as the interpreters still disagree.
+    fn minimize(&self, mut program: Vec<i64>, mut inputs: Vec<i64>) -> (Vec<i64>, Vec<i64>) {
+        loop {
+            let smaller = shrink(&inputs)
+                .map(|inputs| (program.clone(), inputs))
+                .chain(shrink(&program).map(|program| (program, inputs.clone())))
+                .find(|(program, inputs)| self.check(program, inputs).is_some());
+            match smaller {
+                Some(smaller) => (program, inputs) = smaller,
+                None => return (program, inputs),
+            }
+        }
+    }
+}
+
+/// Every way to make `values` one step smaller, biggest cuts first.
+fn shrink(values: &[i64]) -> impl Iterator<Item = Vec<i64>> + '_ {
+    // Whole instructions first, which take up to four words.
+    let removed = (1..=4).rev().flat_map(move |len| {
+        (0..(values.len() + 1).saturating_sub(len)).map(move |at| {
+            let mut smaller = values.to_vec();
+            smaller.drain(at..at + len);
+            smaller
+        })
+    });
+    let halved = (0..values.len())
+        .flat_map(move |at| vec![(at, 0), (at, values[at] / 2)])
+        .filter(move |&(at, value)| value != values[at])
+        .map(move |(at, value)| {
+            let mut smaller = values.to_vec();
+            smaller[at] = value;
+            smaller
+        });
+    removed.chain(halved)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    /// Checks that `program` decodes into instructions.
+    fn decodes(program: &[i64]) -> bool {
+        let mut at = 0;
+        while at < program.len() {
+            match Parameter::try_from(program[at])
+                .ok()
+                .and_then(Parameter::arg_count)
+            {
+                Some(0) => return true,
+                Some(count) => at += 1 + count,
+                None => return false,
+            }
+        }
+        false
+    }
+
+    #[test]
+    fn test_generate() {
+        let mut fuzzer = Fuzzer::new(7);
+        fuzzer.instructions(5).inputs(2);
+        for _ in 0..100 {
+            let (program, inputs) = fuzzer.generate();
+            assert!(decodes(&program), "{:?}", program);
+            assert_eq!(inputs.len(), 2);
+        }
+    }
+
+    #[test]
+    fn test_agree() {
+        let mut fuzzer = Fuzzer::new(2019);
+        assert_eq!(fuzzer.run(500), None);
+    }
+
+    #[test]
+    fn test_minimize() {
+        // Loses every negative output.
+        let lossy: Interpreter = |program, inputs, budget| {
+            let mut outcome = run::<i64>(program, inputs, budget, false);
+            outcome.outputs.retain(|&value| value >= 0);
+            outcome
+        };
+        let mut fuzzer = Fuzzer::new(5);
+        fuzzer.interpreter("lossy", lossy);
+        let disagreement = fuzzer.run(100).unwrap();
+        assert_eq!(disagreement.program, vec![10104, -1]);
+        assert!(disagreement.inputs.is_empty());
+        let report = disagreement.to_string();
+        assert!(report.starts_with("program: 10104,-1\ninputs:  \n"));
+        assert!(report.contains(
+            "    lossy: failed with unknown opcode, outputs [], memory {0: 10104, 1: -1}\n"
+        ));
+    }
+}
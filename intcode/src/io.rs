@@ -0,0 +1,175 @@
+use crate::word::Word;
+
+use std::{
+    collections::VecDeque,
+    io::{self, BufRead, Write},
+    sync::mpsc::{Receiver, Sender},
+};
+
+/// Where opcode 3 reads its values from.
+pub trait Input<W = i64> {
+    /// The next value, or `None` if no value is available yet.
+    fn read_value(&mut self) -> io::Result<Option<W>>;
+}
+
+/// Where opcode 4 writes its values to.
+pub trait Output<W = i64> {
+    fn write_value(&mut self, value: W) -> io::Result<()>;
+}
+
+impl<W, T: Input<W> + ?Sized> Input<W> for &mut T {
+    fn read_value(&mut self) -> io::Result<Option<W>> {
+        (**self).read_value()
+    }
+}
+
+impl<W, T: Output<W> + ?Sized> Output<W> for &mut T {
+    fn write_value(&mut self, value: W) -> io::Result<()> {
+        (**self).write_value(value)
+    }
+}
+
+impl<W> Input<W> for VecDeque<W> {
+    fn read_value(&mut self) -> io::Result<Option<W>> {
+        Ok(self.pop_front())
+    }
+}
+
+impl<W> Output<W> for VecDeque<W> {
+    fn write_value(&mut self, value: W) -> io::Result<()> {
+        self.push_back(value);
+        Ok(())
+    }
+}
+
+impl<W> Output<W> for Vec<W> {
+    fn write_value(&mut self, value: W) -> io::Result<()> {
+        self.push(value);
+        Ok(())
+    }
+}
+
+/// Reads from a channel without waiting, so an empty channel blocks the machine.
+impl<W> Input<W> for Receiver<W> {
+    fn read_value(&mut self) -> io::Result<Option<W>> {
+        Ok(self.try_recv().ok())
+    }
+}
+
+impl<W: Word> Output<W> for Sender<W> {
+    fn write_value(&mut self, value: W) -> io::Result<()> {
+        self.send(value)
+            .map_err(|err| io::Error::new(io::ErrorKind::BrokenPipe, err))
+    }
+}
+
+/// Input produced by a closure.
+#[derive(Debug, Clone)]
+pub struct InputFn<F>(pub F);
+
+impl<W, F: FnMut() -> Option<W>> Input<W> for InputFn<F> {
+    fn read_value(&mut self) -> io::Result<Option<W>> {
+        Ok((self.0)())
+    }
+}
+
+/// Output handed to a closure.
+#[derive(Debug, Clone)]
+pub struct OutputFn<F>(pub F);
+
+impl<W, F: FnMut(W)> Output<W> for OutputFn<F> {
+    fn write_value(&mut self, value: W) -> io::Result<()> {
+        (self.0)(value);
+        Ok(())
+    }
+}
+
+/// Input read as one decimal value per line, such as from stdin.
+#[derive(Debug)]
+pub struct TextInput<R>(pub R);
+
+impl<W: Word, R: BufRead> Input<W> for TextInput<R> {
+    fn read_value(&mut self) -> io::Result<Option<W>> {
+        let mut line = String::new();
+        match self.0.read_line(&mut line)? {
+            0 => Ok(None),
+            _ => match W::parse(line.trim()) {
+                Ok(n) => Ok(Some(n)),
+                Err(err) => Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+            },
+        }
+    }
+}
+
+/// Output written as one decimal value per line, such as to stdout.
+#[derive(Debug)]
+pub struct TextOutput<T>(pub T);
+
+impl<W: Word, T: Write> Output<W> for TextOutput<T> {
+    fn write_value(&mut self, value: W) -> io::Result<()> {
+        writeln!(self.0, "{}", value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_vec_deque() {
+        let mut queue = VecDeque::new();
+        queue.write_value(1).unwrap();
+        queue.write_value(2).unwrap();
+        assert_eq!(queue.read_value().unwrap(), Some(1));
+        assert_eq!(queue.read_value().unwrap(), Some(2));
+        assert_eq!(queue.read_value().unwrap(), None);
+    }
+
+    #[test]
+    fn test_channel() {
+        let (mut send, mut recv) = channel::<i64>();
+        assert_eq!(recv.read_value().unwrap(), None);
+        send.write_value(3).unwrap();
+        assert_eq!(recv.read_value().unwrap(), Some(3));
+        drop(recv);
+        assert_eq!(
+            send.write_value(4).unwrap_err().kind(),
+            io::ErrorKind::BrokenPipe
+        );
+    }
+
+    #[test]
+    fn test_closures() {
+        let mut n = 0;
+        let mut inn = InputFn(|| {
+            n += 1;
+            Some(n)
+        });
+        assert_eq!(inn.read_value().unwrap(), Some(1));
+        assert_eq!(inn.read_value().unwrap(), Some(2));
+
+        let mut seen = Vec::new();
+        let mut out = OutputFn(|v| seen.push(v));
+        out.write_value(5).unwrap();
+        assert_eq!(seen, vec![5]);
+    }
+
+    #[test]
+    fn test_text() {
+        let mut inn = TextInput("12\n-3\nx\n".as_bytes());
+        assert_eq!(inn.read_value().unwrap(), Some(12_i64));
+        assert_eq!(inn.read_value().unwrap(), Some(-3_i64));
+        assert_eq!(
+            Input::<i64>::read_value(&mut inn).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+        assert_eq!(inn.read_value().unwrap(), None::<i64>);
+
+        let mut out = TextOutput(Vec::new());
+        out.write_value(7_i64).unwrap();
+        out.write_value(-8_i64).unwrap();
+        assert_eq!(out.0, b"7\n-8\n");
+    }
+}
@@ -0,0 +1,60 @@
+//! The Intcode computer shared by every day that runs Intcode programs.
+//!
+//! A program is loaded into a [`Machine`], which is then driven with
+//! [`Machine::run`] until it either halts or blocks waiting for input, or
+//! one event at a time with [`Machine::run_until_event`]. A [`Debugger`]
+//! wraps a machine to step through it interactively.
+
+mod amplifier;
+mod ascii;
+mod asm;
+mod cfg;
+mod conformance;
+mod debugger;
+mod disasm;
+mod error;
+mod fuzz;
+mod io;
+mod loader;
+mod machine;
+mod memory;
+mod network;
+mod parameter;
+mod polynomial;
+mod profile;
+mod snapshot;
+mod symbolic;
+mod threaded;
+mod trace;
+mod transpile;
+mod word;
+
+pub use amplifier::{AmplifierChain, ChainError, Mode, Setting};
+pub use ascii::{run_script, AsciiInput, AsciiOutput, Transcript};
+pub use asm::{assemble, AsmError};
+pub use cfg::{Block, Cfg, Exit, Instruction, Target};
+pub use conformance::{parse_cases, Case, Failure};
+pub use debugger::Debugger;
+pub use disasm::{disassemble, format_instruction, label_name, Line, Listing};
+pub use error::{Error, ErrorKind};
+pub use fuzz::{interpreters, Disagreement, Fuzzer, Interpreter, Outcome};
+pub use io::{Input, InputFn, Output, OutputFn, TextInput, TextOutput};
+pub use loader::{load, parse_program, read_program, to_image, LoadError, ParseError};
+pub use machine::{Event, Machine, Status};
+pub use memory::{Memory, MemoryLimitExceeded, DEFAULT_MEMORY_LIMIT};
+pub use network::{MachineId, Network, NetworkError, Outputs};
+pub use parameter::{Parameter, ParameterMode};
+pub use polynomial::{evaluate_symbolic, Linear, Polynomial, SymbolicError};
+pub use profile::{Profile, Profiler};
+pub use snapshot::{Snapshot, SnapshotError};
+pub use symbolic::{Constraint, End, Expr, Path, SymbolicExecutor, SymbolicOutput};
+pub use threaded::ThreadedMachine;
+pub use trace::{
+    read_trace, replay, Divergence, MemoryWrite, TraceEntry, TraceError, Traced, Tracer,
+};
+pub use transpile::{transpile, TranspileError, Unsupported};
+pub use word::Word;
+
+// Lets tests compile transpiled modules, which refer to this crate by name.
+#[cfg(test)]
+extern crate self as intcode;
@@ -0,0 +1,195 @@
+// The impls generated by `err-derive` live inside an anonymous const.
+#![allow(non_local_definitions)]
+
+use crate::snapshot::binary;
+
+use bincode::Options;
+use err_derive::Error;
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::Path,
+    str,
+};
+
+/// Leads every program image, followed by its version.
+const MAGIC: &[u8; 4] = b"ICP\x01";
+
+/// Where and why the text form of a program could not be parsed.
+#[derive(Debug, Error, PartialEq, Eq, Clone)]
+#[error(display = "line {}, column {}: {} `{}`", line, column, message, token)]
+pub struct ParseError {
+    /// Counted from 1, like `column`.
+    pub line: usize,
+    pub column: usize,
+    pub token: String,
+    pub message: String,
+}
+
+/// What went wrong while loading a program.
+#[derive(Debug, Error)]
+pub enum LoadError {
+    #[error(display = "I/O error: {}", _0)]
+    Io(io::Error),
+    #[error(display = "{}", _0)]
+    Parse(ParseError),
+    #[error(display = "the program is neither text nor an image")]
+    NotText,
+    #[error(display = "malformed program image: {}", _0)]
+    Image(String),
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+impl From<ParseError> for LoadError {
+    fn from(err: ParseError) -> Self {
+        LoadError::Parse(err)
+    }
+}
+
+/// Parses the comma separated text form of a program.
+///
+/// Whitespace and line breaks may surround every value, the last value may
+/// be followed by a comma, and `#` comments out the rest of its line.
+pub fn parse_program(text: &str) -> Result<Vec<i64>, ParseError> {
+    let mut program = Vec::new();
+    let mut after_value = false;
+    for (line, content) in text.lines().enumerate() {
+        let content = match content.find('#') {
+            Some(comment) => &content[..comment],
+            None => content,
+        };
+        let error = |start: usize, token: &str, message: &str| ParseError {
+            line: line + 1,
+            column: content[..start].chars().count() + 1,
+            token: token.to_string(),
+            message: message.to_string(),
+        };
+        let mut rest = content.char_indices().peekable();
+        while let Some((start, c)) = rest.next() {
+            match c {
+                c if c.is_whitespace() => (),
+                ',' => match after_value {
+                    true => after_value = false,
+                    false => return Err(error(start, ",", "expected a value before")),
+                },
+                _ => {
+                    let mut end = start + c.len_utf8();
+                    while let Some(&(at, c)) = rest.peek() {
+                        if c.is_whitespace() || c == ',' {
+                            break;
+                        }
+                        end = at + c.len_utf8();
+                        rest.next();
+                    }
+                    let token = &content[start..end];
+                    if after_value {
+                        return Err(error(start, token, "expected a comma before"));
+                    }
+                    let value = token
+                        .parse()
+                        .map_err(|_| error(start, token, "expected a number, found"))?;
+                    program.push(value);
+                    after_value = true;
+                }
+            }
+        }
+    }
+    Ok(program)
+}
+
+/// Encodes a program in the compact binary image format.
+pub fn to_image(program: &[i64]) -> Vec<u8> {
+    let mut bytes = MAGIC.to_vec();
+    bytes.extend(binary().serialize(program).unwrap());
+    bytes
+}
+
+/// Decodes a program from either its binary image or its text form.
+pub fn read_program(bytes: &[u8]) -> Result<Vec<i64>, LoadError> {
+    match bytes.strip_prefix(&MAGIC[..]) {
+        Some(image) => binary()
+            .deserialize(image)
+            .map_err(|err| LoadError::Image(err.to_string())),
+        None => {
+            let text = str::from_utf8(bytes).map_err(|_| LoadError::NotText)?;
+            Ok(parse_program(text)?)
+        }
+    }
+}
+
+/// Loads a program from a file, or from stdin if `path` is `-`.
+pub fn load(path: impl AsRef<Path>) -> Result<Vec<i64>, LoadError> {
+    let path = path.as_ref();
+    let mut bytes = Vec::new();
+    match path == Path::new("-") {
+        true => io::stdin().lock().read_to_end(&mut bytes)?,
+        false => File::open(path)?.read_to_end(&mut bytes)?,
+    };
+    read_program(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text() {
+        let text = "# day 2\n1, 0,0,3,\n  2,3,11,0, # multiply\n\n99,\n";
+        assert_eq!(
+            parse_program(text).unwrap(),
+            vec![1, 0, 0, 3, 2, 3, 11, 0, 99]
+        );
+        assert!(parse_program(" \n# nothing\n").unwrap().is_empty());
+
+        let error = |text| {
+            let err = parse_program(text).unwrap_err();
+            (err.line, err.column, err.token, err.message)
+        };
+        assert_eq!(
+            error("1,2,\n3,x4,5"),
+            (2, 3, "x4".into(), "expected a number, found".into())
+        );
+        assert_eq!(
+            error("1,\n  ,2"),
+            (2, 3, ",".into(), "expected a value before".into())
+        );
+        assert_eq!(
+            error("1,2 3"),
+            (1, 5, "3".into(), "expected a comma before".into())
+        );
+        assert_eq!(
+            parse_program("99,é").unwrap_err().to_string(),
+            "line 1, column 4: expected a number, found `é`"
+        );
+    }
+
+    #[test]
+    fn test_image() {
+        let program = vec![1, 0, 0, 3, 99, -1, i64::MAX, i64::MIN];
+        let image = to_image(&program);
+        // The length and every small value take a single byte.
+        assert_eq!(image.len(), 4 + 1 + 6 + 2 * 9);
+        assert_eq!(read_program(&image).unwrap(), program);
+        assert_eq!(read_program(b"1,2,99\n").unwrap(), vec![1, 2, 99]);
+        assert!(matches!(
+            read_program(&image[..image.len() - 1]),
+            Err(LoadError::Image(_))
+        ));
+        assert!(matches!(
+            read_program(&[0xff, 0xfe]),
+            Err(LoadError::NotText)
+        ));
+    }
+
+    #[test]
+    fn test_load() {
+        let program = load("../day_02/input.txt").unwrap();
+        assert_eq!(program[..4], [1, 0, 0, 3]);
+        assert!(matches!(load("missing.txt"), Err(LoadError::Io(_))));
+    }
+}
@@ -0,0 +1,492 @@
+use crate::{
+    error::{Error, ErrorKind},
+    io::{Input, Output},
+    memory::Memory,
+    parameter::Parameter,
+    snapshot::Snapshot,
+    word::Word,
+};
+
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, convert::TryFrom, mem};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum Status {
+    /// The machine has been created, but no instruction has been run.
+    NotYetStarted,
+    /// The last instruction finished and the machine can continue.
+    Running,
+    /// The machine is waiting on an input that is not yet available.
+    Blocking,
+    /// The machine has reached opcode 99.
+    Halted,
+}
+
+/// Why [`Machine::run_until_event`] handed control back to the caller.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Event<W = i64> {
+    /// The machine is waiting for [`Machine::push_input`].
+    NeedsInput,
+    /// The machine produced a value.
+    Output(W),
+    /// The machine has reached opcode 99.
+    Halted,
+}
+
+/// An Intcode computer owning its program memory.
+///
+/// Machines compute with `i64` unless another [`Word`] is chosen, such as
+/// `Machine::<i128>::from(program)`.
+#[derive(Debug, Clone)]
+pub struct Machine<W = i64> {
+    pub(crate) memory: Memory<W>,
+    pub(crate) ip: usize,
+    pub(crate) relative_base: W,
+    pub(crate) status: Status,
+    pub(crate) input: VecDeque<W>,
+}
+
+impl<W: Word> From<Vec<W>> for Machine<W> {
+    fn from(program: Vec<W>) -> Self {
+        Self {
+            memory: program.into(),
+            ip: 0,
+            relative_base: *W::ZERO,
+            status: Status::NotYetStarted,
+            input: VecDeque::new(),
+        }
+    }
+}
+
+impl Machine {
+    pub fn new(program: Vec<i64>) -> Self {
+        program.into()
+    }
+
+    /// Captures the full state of the machine, including its pending input.
+    pub fn snapshot(&self) -> Snapshot {
+        let (memory, sparse_memory) = self.memory.to_parts();
+        Snapshot {
+            memory,
+            sparse_memory,
+            memory_limit: self.memory.limit(),
+            ip: self.ip,
+            relative_base: self.relative_base,
+            status: self.status,
+            input: self.input.iter().cloned().collect(),
+        }
+    }
+
+    /// Puts the machine back into the state captured by `snapshot`.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.memory = Memory::from_parts(
+            snapshot.memory.clone(),
+            &snapshot.sparse_memory,
+            snapshot.memory_limit,
+        );
+        self.ip = snapshot.ip;
+        self.relative_base = snapshot.relative_base;
+        self.status = snapshot.status;
+        self.input = snapshot.input.iter().cloned().collect();
+    }
+}
+
+impl<W: Word> Machine<W> {
+    /// Makes any access at or above `limit` fail instead of growing memory.
+    pub fn with_memory_limit(mut self, limit: usize) -> Self {
+        self.memory.set_limit(limit);
+        self
+    }
+
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    pub fn relative_base(&self) -> W {
+        self.relative_base
+    }
+
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    pub fn memory(&self) -> &Memory<W> {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory<W> {
+        &mut self.memory
+    }
+
+    /// Queues a value for [`Machine::run_until_event`] to read.
+    pub fn push_input(&mut self, value: W) {
+        self.input.push_back(value);
+    }
+
+    /// Input that has been queued, but not yet read by the program.
+    pub fn pending_input(&self) -> &VecDeque<W> {
+        &self.input
+    }
+
+    /// Runs a single instruction, reading from `inn` and writing to `out`.
+    pub fn step<I: Input<W> + ?Sized, O: Output<W> + ?Sized>(
+        &mut self,
+        inn: &mut I,
+        out: &mut O,
+    ) -> Result<Status, Error> {
+        if self.status == Status::Halted {
+            return Ok(Status::Halted);
+        }
+        let ip = self.ip;
+        self.status = self
+            .memory
+            .get(ip)
+            .map_err(ErrorKind::from)
+            .and_then(|instruction| {
+                i64::try_from(instruction.to_i128()).map_err(|_| ErrorKind::UnknownOpcode)
+            })
+            .and_then(Parameter::try_from)
+            .and_then(|param: Parameter| {
+                param.run(
+                    &mut self.ip,
+                    &mut self.relative_base,
+                    &mut self.memory,
+                    inn,
+                    out,
+                )
+            })
+            .map_err(|kind| self.error(ip, kind))?;
+        Ok(self.status)
+    }
+
+    pub(crate) fn error(&self, ip: usize, kind: ErrorKind) -> Error {
+        Error {
+            ip,
+            instruction: self.memory[ip].to_i128(),
+            kind,
+        }
+    }
+
+    /// Runs until the machine halts or blocks on input.
+    ///
+    /// A blocked machine can be resumed by calling `run` again once more
+    /// input is available.
+    pub fn run<I: Input<W> + ?Sized, O: Output<W> + ?Sized>(
+        &mut self,
+        inn: &mut I,
+        out: &mut O,
+    ) -> Result<Status, Error> {
+        loop {
+            match self.step(inn, out)? {
+                Status::Running => (),
+                status => return Ok(status),
+            }
+        }
+    }
+
+    /// Runs until the machine halts, treating running out of input as an error.
+    pub fn run_to_halt<I: Input<W> + ?Sized, O: Output<W> + ?Sized>(
+        &mut self,
+        inn: &mut I,
+        out: &mut O,
+    ) -> Result<(), Error> {
+        match self.run(inn, out)? {
+            Status::Blocking => Err(self.error(self.ip, ErrorKind::InputExhausted)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Runs on the queued input until the machine outputs a value, needs
+    /// more input or halts.
+    ///
+    /// After [`Event::NeedsInput`] the machine continues where it stopped
+    /// once a value has been given with [`Machine::push_input`].
+    pub fn run_until_event(&mut self) -> Result<Event<W>, Error> {
+        let mut inn = mem::take(&mut self.input);
+        let mut out = Vec::with_capacity(1);
+        let event = loop {
+            match self.step(&mut inn, &mut out) {
+                Ok(Status::Running) => {
+                    if let Some(value) = out.pop() {
+                        break Ok(Event::Output(value));
+                    }
+                }
+                Ok(Status::Blocking) => break Ok(Event::NeedsInput),
+                Ok(Status::Halted) => break Ok(Event::Halted),
+                Ok(Status::NotYetStarted) => unreachable!("A stepped machine has started"),
+                Err(err) => break Err(err),
+            }
+        };
+        self.input = inn;
+        event
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm::assemble, loader::parse_program};
+
+    use std::{collections::VecDeque, io, num::Wrapping};
+
+    fn run_with_input(program: &[i64], input: &[i64]) -> Vec<i64> {
+        let mut machine = Machine::new(program.to_vec());
+        let mut out = Vec::new();
+        machine
+            .run(
+                &mut input.iter().cloned().collect::<VecDeque<_>>(),
+                &mut out,
+            )
+            .unwrap();
+        out
+    }
+
+    #[test]
+    fn test_step() {
+        let mut machine = Machine::new(vec![1002, 4, 3, 4, 33]);
+        let status = machine.step(&mut VecDeque::new(), &mut Vec::new()).unwrap();
+        assert_eq!(status, Status::Running);
+        assert_eq!(machine.memory()[4], 99);
+        let status = machine.step(&mut VecDeque::new(), &mut Vec::new()).unwrap();
+        assert_eq!(status, Status::Halted);
+    }
+
+    #[test]
+    fn test_add_and_multiply() {
+        let mut machine = Machine::new(vec![1, 9, 10, 3, 2, 3, 11, 0, 99, 30, 40, 50]);
+        machine.run(&mut VecDeque::new(), &mut Vec::new()).unwrap();
+        assert_eq!(machine.memory()[0], 3500);
+    }
+
+    #[test]
+    fn test_input_is_written_to_first_argument() {
+        let mut machine = Machine::new(vec![3, 0, 4, 0, 99]);
+        let mut out = Vec::new();
+        machine
+            .run(&mut VecDeque::from(vec![42]), &mut out)
+            .unwrap();
+        assert_eq!(out, vec![42]);
+        assert_eq!(machine.memory()[0], 42);
+    }
+
+    #[test]
+    fn test_blocking_and_resume() {
+        let mut machine = Machine::new(vec![3, 0, 4, 0, 99]);
+        let mut inn = VecDeque::new();
+        let mut out = Vec::new();
+        assert_eq!(machine.run(&mut inn, &mut out).unwrap(), Status::Blocking);
+        inn.push_back(7);
+        assert_eq!(machine.run(&mut inn, &mut out).unwrap(), Status::Halted);
+        assert_eq!(out, vec![7]);
+    }
+
+    #[test]
+    fn test_events() {
+        let program = assemble(
+            "
+            start:  in -> [x]
+                    out #1
+                    out [x]
+                    jnz #1, start
+            x:      .data 0
+            ",
+        )
+        .unwrap();
+        let mut machine = Machine::new(program);
+        assert_eq!(machine.run_until_event().unwrap(), Event::NeedsInput);
+        assert_eq!(machine.run_until_event().unwrap(), Event::NeedsInput);
+        machine.push_input(5);
+        machine.push_input(6);
+        assert_eq!(machine.run_until_event().unwrap(), Event::Output(1));
+        assert_eq!(machine.run_until_event().unwrap(), Event::Output(5));
+        assert_eq!(machine.pending_input(), &[6]);
+        assert_eq!(machine.run_until_event().unwrap(), Event::Output(1));
+        assert_eq!(machine.run_until_event().unwrap(), Event::Output(6));
+        assert_eq!(machine.run_until_event().unwrap(), Event::NeedsInput);
+        assert_eq!(machine.status(), Status::Blocking);
+
+        let mut machine = Machine::new(vec![104, 3, 99]);
+        assert_eq!(machine.run_until_event().unwrap(), Event::Output(3));
+        assert_eq!(machine.run_until_event().unwrap(), Event::Halted);
+        assert_eq!(machine.run_until_event().unwrap(), Event::Halted);
+        assert_eq!(machine.status(), Status::Halted);
+    }
+
+    #[test]
+    fn test_compare_and_jump() {
+        let larger_than_8 = [
+            3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0,
+            0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4,
+            20, 1105, 1, 46, 98, 99,
+        ];
+        assert_eq!(run_with_input(&larger_than_8, &[7]), vec![999]);
+        assert_eq!(run_with_input(&larger_than_8, &[8]), vec![1000]);
+        assert_eq!(run_with_input(&larger_than_8, &[9]), vec![1001]);
+    }
+
+    #[test]
+    fn test_relative_base() {
+        let mut machine = Machine::new(vec![109, 19, 204, -34, 99]);
+        machine.memory_mut().set(1985, 7).unwrap();
+        machine.relative_base = 2000;
+        let mut out = Vec::new();
+        machine.run(&mut VecDeque::new(), &mut out).unwrap();
+        assert_eq!(machine.relative_base(), 2019);
+        assert_eq!(out, vec![7]);
+    }
+
+    #[test]
+    fn test_quine() {
+        let quine = [
+            109, 1, 204, -1, 1001, 100, 1, 100, 1008, 100, 16, 101, 1006, 101, 0, 99,
+        ];
+        assert_eq!(run_with_input(&quine, &[]), quine);
+    }
+
+    #[test]
+    fn test_large_numbers() {
+        let out = run_with_input(&[1102, 34915192, 34915192, 7, 4, 7, 99, 0], &[]);
+        assert_eq!(out[0].to_string().len(), 16);
+        let out = run_with_input(&[104, 1125899906842624, 99], &[]);
+        assert_eq!(out, vec![1125899906842624]);
+    }
+
+    /// Squares its input and outputs the result.
+    const SQUARE: [i64; 9] = [3, 0, 2, 0, 0, 0, 4, 0, 99];
+
+    fn square<W: Word>(value: W) -> Result<W, Error> {
+        let program: Vec<_> = SQUARE.iter().map(|&n| W::from_i64(n)).collect();
+        let mut machine = Machine::<W>::from(program);
+        let mut out = Vec::new();
+        machine.run(&mut VecDeque::from(vec![value]), &mut out)?;
+        Ok(out[0])
+    }
+
+    #[test]
+    fn test_word_types() {
+        let big: i64 = 1 << 40;
+        let err = square(big).unwrap_err();
+        assert_eq!((err.ip, err.instruction), (2, 2));
+        assert_eq!(err.kind, ErrorKind::ArithmeticOverflow);
+        assert_eq!(square(big as i128), Ok(1 << 80));
+        assert_eq!(square(Wrapping(big)), Ok(Wrapping(0)));
+        assert_eq!(square(Wrapping(-(1_i128 << 64))), Ok(Wrapping(0)));
+
+        // Words that do not fit an address or opcode are reported in full.
+        let mut machine = Machine::<i128>::from(vec![1 << 70]);
+        let err = machine
+            .run(&mut VecDeque::new(), &mut Vec::new())
+            .unwrap_err();
+        assert_eq!(
+            (err.instruction, err.kind),
+            (1 << 70, ErrorKind::UnknownOpcode)
+        );
+        let mut machine = Machine::<i128>::from(vec![1105, 1, 1 << 70]);
+        let err = machine
+            .run(&mut VecDeque::new(), &mut Vec::new())
+            .unwrap_err();
+        assert_eq!(
+            (err.ip, err.kind),
+            (0, ErrorKind::AddressOutOfRange(1 << 70))
+        );
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        let mut machine = Machine::new(vec![1101, 1, 1, 5000, 99]).with_memory_limit(4096);
+        let err = machine
+            .run(&mut VecDeque::new(), &mut Vec::new())
+            .unwrap_err();
+        assert_eq!(
+            err.kind,
+            ErrorKind::MemoryLimit {
+                address: 5000,
+                limit: 4096
+            }
+        );
+
+        // A jump not taken at the very top of memory moves past every address.
+        let mut machine = Machine::new(vec![99]).with_memory_limit(usize::MAX);
+        machine.memory_mut().set(usize::MAX - 2, 1105).unwrap();
+        let mut snapshot = machine.snapshot();
+        snapshot.ip = usize::MAX - 2;
+        machine.restore(&snapshot);
+        let err = machine
+            .run(&mut VecDeque::new(), &mut Vec::new())
+            .unwrap_err();
+        assert_eq!(
+            (err.ip, err.kind),
+            (
+                usize::MAX - 2,
+                ErrorKind::MemoryLimit {
+                    address: usize::MAX,
+                    limit: usize::MAX
+                }
+            )
+        );
+    }
+
+    fn run_error(program: &[i64], input: &[i64]) -> Error {
+        let mut machine = Machine::new(program.to_vec());
+        machine
+            .run_to_halt(
+                &mut input.iter().cloned().collect::<VecDeque<_>>(),
+                &mut Vec::new(),
+            )
+            .unwrap_err()
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = run_error(&[1, 0, 0, 0, 42], &[]);
+        assert_eq!(
+            err,
+            Error {
+                ip: 4,
+                instruction: 42,
+                kind: ErrorKind::UnknownOpcode
+            }
+        );
+        let err = run_error(&[1, 0, 0, 0, 301], &[]);
+        assert_eq!(err.kind, ErrorKind::InvalidParameterMode(3));
+        assert_eq!(err.ip, 4);
+        assert_eq!(err.instruction, 301);
+        assert_eq!(
+            run_error(&[1, -1, 0, 0, 99], &[]).kind,
+            ErrorKind::NegativeAddress(-1)
+        );
+        assert_eq!(
+            run_error(&[11101, 1, 1, 0, 99], &[]).kind,
+            ErrorKind::WriteInImmediateMode
+        );
+        assert_eq!(
+            run_error(&[1, 0, 0, 0, 3, 0, 3, 0, 99], &[1]).kind,
+            ErrorKind::InputExhausted
+        );
+        assert_eq!(
+            run_error(&[1102, i64::MAX, 2, 0, 99], &[]).kind,
+            ErrorKind::ArithmeticOverflow
+        );
+        assert_eq!(
+            run_error(&[1105, 1, -4, 99], &[]).kind,
+            ErrorKind::NegativeAddress(-4)
+        );
+    }
+
+    #[test]
+    fn test_output_errors_are_reported() {
+        let (send, recv) = std::sync::mpsc::channel();
+        drop(recv);
+        let mut machine = Machine::new(vec![104, 1, 99]);
+        let err = machine
+            .run(&mut VecDeque::new(), &mut send.clone())
+            .unwrap_err();
+        assert_eq!(err.kind, ErrorKind::Io(io::ErrorKind::BrokenPipe));
+    }
+
+    #[test]
+    fn test_parse_program() {
+        assert_eq!(parse_program("1,0,-3,99\n").unwrap(), vec![1, 0, -3, 99]);
+        assert!(parse_program("1,,2").is_err());
+    }
+}
@@ -0,0 +1,166 @@
+use intcode::{
+    assemble, disassemble, load, read_trace, replay, run_script, to_image, transpile, AsciiInput,
+    AsciiOutput, Cfg, Debugger, Fuzzer, Machine, Profiler, Status, SymbolicExecutor, TextInput,
+    TextOutput, ThreadedMachine, Tracer,
+};
+
+use std::{
+    env,
+    error::Error,
+    fs::{read_to_string, File},
+    io::{self, BufReader, BufWriter, Write},
+    process,
+};
+
+const USAGE: &str = "\
+usage: intcode <command> <file> [<trace> | <script> | <entry>...]
+
+<file> is a program as text or image, or - to read it from stdin for commands
+that take no input.
+
+commands:
+    asm       assemble mnemonic source into a comma separated program
+    image     convert the program into the compact binary image format
+    disasm    print an annotated listing of the program
+    cfg       print the control-flow graph from address 0 and any extra entries as DOT
+    debug     step through the program, reading commands from stdin
+    ascii     run the program as text from stdin, or on the commands of a script
+    trace     run the program on input from stdin, writing a JSON Lines trace
+    profile   run the program on input from stdin, reporting where it spends its time
+    folded    like profile, but print basic block counts for flamegraph tools
+    replay    check a trace against a fresh run of the program, on the pre-decoded
+              interpreter if `threaded` follows the trace
+    transpile print the program as a Rust module, if it never modifies its code
+    symbolic  print every path through the program on symbolic inputs, with its outputs
+    fuzz      compare every interpreter on random programs, taking a seed instead of <file>";
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["asm", path] => {
+            let program = assemble(&read_to_string(path)?)?;
+            let text: Vec<_> = program.iter().map(i64::to_string).collect();
+            println!("{}", text.join(","));
+        }
+        ["image", path] => {
+            io::stdout().write_all(&to_image(&load(path)?))?;
+        }
+        ["disasm", path] => {
+            let program = load(path)?;
+            print!("{}", disassemble(&program));
+        }
+        ["cfg", path, entries @ ..] => {
+            let program = load(path)?;
+            let mut starts = vec![0];
+            for entry in entries {
+                starts.push(entry.parse()?);
+            }
+            print!("{}", Cfg::with_entries(&program, &starts).to_dot());
+        }
+        ["debug", path] => {
+            let program = load(path)?;
+            let stdin = io::stdin();
+            Debugger::new(Machine::new(program)).repl(stdin.lock(), io::stdout())?;
+        }
+        ["ascii", path] => {
+            let stdin = io::stdin();
+            let mut out = AsciiOutput::new(io::stdout());
+            Machine::new(load(path)?).run_to_halt(&mut AsciiInput::new(stdin.lock()), &mut out)?;
+            for result in out.results {
+                println!("{}", result);
+            }
+        }
+        ["ascii", path, script] => {
+            let transcript = run_script(&mut Machine::new(load(path)?), &read_to_string(script)?)?;
+            print!("{}", transcript.text);
+            for result in transcript.results {
+                println!("{}", result);
+            }
+            if transcript.status != Status::Halted {
+                eprintln!("the script ended while the program waits for input");
+                process::exit(1);
+            }
+        }
+        ["trace", path] => {
+            let program = load(path)?;
+            let stdin = io::stdin();
+            let mut tracer = Tracer::new(BufWriter::new(io::stdout()));
+            tracer.run(
+                &mut Machine::new(program),
+                &mut TextInput(stdin.lock()),
+                &mut TextOutput(io::stderr()),
+            )?;
+            tracer.into_inner().flush()?;
+        }
+        [command @ "profile", path] | [command @ "folded", path] => {
+            let program = load(path)?;
+            let stdin = io::stdin();
+            let mut profiler = Profiler::new();
+            profiler.run(
+                &mut Machine::new(program),
+                &mut TextInput(stdin.lock()),
+                &mut TextOutput(io::stderr()),
+            )?;
+            match *command {
+                "profile" => print!("{}", profiler.profile().report(20)),
+                _ => print!("{}", profiler.profile().folded()),
+            }
+        }
+        ["replay", path, trace] | ["replay", path, trace, "threaded"] => {
+            let machine = Machine::new(load(path)?);
+            let trace = read_trace(BufReader::new(File::open(trace)?))?;
+            let result = match args.len() {
+                3 => replay(machine, &trace),
+                _ => replay(ThreadedMachine::from(machine), &trace),
+            };
+            match result {
+                Ok(steps) => println!("all {} steps match", steps),
+                Err(divergence) => {
+                    println!("{}", divergence);
+                    process::exit(1);
+                }
+            }
+        }
+        ["transpile", path] => {
+            let program = load(path)?;
+            match transpile(&program) {
+                Ok(module) => print!("{}", module),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                }
+            }
+        }
+        ["symbolic", path] => {
+            let executor = SymbolicExecutor::new(load(path)?);
+            for (n, path) in executor.explore().iter().enumerate() {
+                let constraints: Vec<_> = path.constraints.iter().map(|c| c.to_string()).collect();
+                println!("path {}: {}", n, constraints.join(", "));
+                for output in &path.outputs {
+                    println!("    out {}", output.value);
+                }
+                match executor.solve(&path.constraints, path.inputs) {
+                    Some(inputs) => println!("    end {:?}, for example on {:?}", path.end, inputs),
+                    None => println!("    end {:?}", path.end),
+                }
+            }
+        }
+        ["fuzz"] | ["fuzz", _] => {
+            let seed = args.get(1).map_or(Ok(0), |seed| seed.parse())?;
+            let programs = 10_000;
+            match Fuzzer::new(seed).run(programs) {
+                Some(disagreement) => {
+                    print!("{}", disagreement);
+                    process::exit(1);
+                }
+                None => println!("all interpreters agree on {} programs", programs),
+            }
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,248 @@
+use crate::word::Word;
+
+use std::{collections::BTreeMap, error::Error, fmt, ops::Index, sync::Arc};
+
+/// The highest address (exclusive) a machine may touch unless configured otherwise.
+pub const DEFAULT_MEMORY_LIMIT: usize = 1 << 24;
+
+/// Written memory is copied in pages of this many cells.
+const PAGE_SIZE: usize = 256;
+
+/// An access was made at or above the configured memory limit.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct MemoryLimitExceeded {
+    pub address: usize,
+    pub limit: usize,
+}
+
+impl fmt::Display for MemoryLimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Address {} is outside of the memory limit of {}",
+            self.address, self.limit
+        )
+    }
+}
+
+impl Error for MemoryLimitExceeded {}
+
+/// A copied page of memory, shared between clones until one of them writes to it.
+type Page<W> = Arc<[W; PAGE_SIZE]>;
+
+/// Program memory that reads as zero everywhere it has not been written.
+///
+/// The loaded program image is shared between every clone of a memory, and
+/// a page is only copied once it is written to. Cloning is therefore cheap
+/// no matter how large the program is or how far away it writes, which
+/// keeps forking machines cheap.
+#[derive(Debug, Clone)]
+pub struct Memory<W = i64> {
+    image: Arc<[W]>,
+    /// Keyed by page number, holding only the pages that have been written.
+    pages: BTreeMap<usize, Page<W>>,
+    limit: usize,
+}
+
+impl<W: Word> From<Vec<W>> for Memory<W> {
+    fn from(image: Vec<W>) -> Self {
+        Arc::<[W]>::from(image).into()
+    }
+}
+
+impl<W: Word> From<Arc<[W]>> for Memory<W> {
+    fn from(image: Arc<[W]>) -> Self {
+        Self {
+            image,
+            pages: BTreeMap::new(),
+            limit: DEFAULT_MEMORY_LIMIT,
+        }
+    }
+}
+
+impl<W: Word> PartialEq for Memory<W> {
+    fn eq(&self, other: &Self) -> bool {
+        self.limit == other.limit && self.non_zero().eq(other.non_zero())
+    }
+}
+
+impl<W: Word> Eq for Memory<W> {}
+
+impl<W: Word> Memory<W> {
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    fn check(&self, address: usize) -> Result<(), MemoryLimitExceeded> {
+        match address < self.limit {
+            true => Ok(()),
+            false => Err(MemoryLimitExceeded {
+                address,
+                limit: self.limit,
+            }),
+        }
+    }
+
+    pub fn get(&self, address: usize) -> Result<W, MemoryLimitExceeded> {
+        self.check(address)?;
+        Ok(self[address])
+    }
+
+    pub fn set(&mut self, address: usize, value: W) -> Result<(), MemoryLimitExceeded> {
+        self.check(address)?;
+        let (page, offset) = (address / PAGE_SIZE, address % PAGE_SIZE);
+        let image = &self.image;
+        let page = self.pages.entry(page).or_insert_with(|| {
+            let mut cells = [*W::ZERO; PAGE_SIZE];
+            let start = (page * PAGE_SIZE).min(image.len());
+            let end = (start + PAGE_SIZE).min(image.len());
+            cells[..end - start].copy_from_slice(&image[start..end]);
+            Arc::new(cells)
+        });
+        Arc::make_mut(page)[offset] = value;
+        Ok(())
+    }
+
+    /// How many pages this memory has copied from the image, or allocated beyond it.
+    pub fn pages_touched(&self) -> usize {
+        self.pages.len()
+    }
+
+    /// Every cell of the loaded program, followed by the non-zero cells
+    /// written beyond it, in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, W)> + '_ {
+        let image = (0..self.image.len()).map(move |a| (a, self[a]));
+        image.chain(self.written_beyond_image())
+    }
+
+    fn written_beyond_image(&self) -> impl Iterator<Item = (usize, W)> + '_ {
+        let first = self.image.len() / PAGE_SIZE;
+        let image_len = self.image.len();
+        self.pages
+            .range(first..)
+            .map(|(&n, page)| (n * PAGE_SIZE, page))
+            .flat_map(|(start, page)| page.iter().enumerate().map(move |(n, &v)| (start + n, v)))
+            .filter(move |&(a, v)| a >= image_len && v != *W::ZERO)
+    }
+
+    fn non_zero(&self) -> impl Iterator<Item = (usize, W)> + '_ {
+        self.iter().filter(|&(_, v)| v != *W::ZERO)
+    }
+
+    /// Splits memory into the cells of the program image and the non-zero
+    /// cells written beyond it.
+    pub(crate) fn to_parts(&self) -> (Vec<W>, Vec<(usize, W)>) {
+        let image = (0..self.image.len()).map(|a| self[a]).collect();
+        (image, self.written_beyond_image().collect())
+    }
+
+    /// Rebuilds memory split by [`Memory::to_parts`].
+    pub(crate) fn from_parts(image: Vec<W>, written: &[(usize, W)], limit: usize) -> Self {
+        let mut memory = Memory::from(image);
+        memory.limit = limit;
+        for &(address, value) in written {
+            // Cells at or above the limit could never be read back anyway.
+            memory.set(address, value).ok();
+        }
+        memory
+    }
+}
+
+impl<W: Word> Index<usize> for Memory<W> {
+    type Output = W;
+
+    fn index(&self, address: usize) -> &W {
+        match self.pages.get(&(address / PAGE_SIZE)) {
+            Some(page) => &page[address % PAGE_SIZE],
+            _ => self.image.get(address).unwrap_or(W::ZERO),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unwritten_memory_is_zero() {
+        let memory = Memory::<i64>::from(vec![1, 2, 3]);
+        assert_eq!(memory[1], 2);
+        assert_eq!(memory[3], 0);
+        assert_eq!(memory.get(1_000_000), Ok(0));
+    }
+
+    #[test]
+    fn test_pages() {
+        let mut memory = Memory::<i64>::from(vec![1, 2, 3]);
+        memory.set(10, 4).unwrap();
+        memory.set(1_000_000, 5).unwrap();
+        assert_eq!(memory.pages_touched(), 2);
+        assert_eq!(memory[2], 3);
+        assert_eq!(memory[9], 0);
+        assert_eq!(memory[10], 4);
+        assert_eq!(memory[1_000_000], 5);
+        assert_eq!(
+            memory.iter().collect::<Vec<_>>(),
+            vec![(0, 1), (1, 2), (2, 3), (10, 4), (1_000_000, 5)]
+        );
+
+        // Equal contents compare equal, however they are laid out.
+        let mut other = Memory::<i64>::from(vec![1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 4]);
+        other.set(1_000_000, 5).unwrap();
+        assert_eq!(memory, other);
+    }
+
+    #[test]
+    fn test_clones_share_pages() {
+        let mut memory = Memory::<i64>::from(vec![7; 3000]);
+        memory.set(0, 1).unwrap();
+        let mut clone = memory.clone();
+        assert!(Arc::ptr_eq(&memory.image, &clone.image));
+        assert!(Arc::ptr_eq(&memory.pages[&0], &clone.pages[&0]));
+
+        clone.set(1, 2).unwrap();
+        clone.set(2500, 3).unwrap();
+        assert_eq!(clone.pages_touched(), 2);
+        assert_eq!((clone[0], clone[1], clone[2], clone[2500]), (1, 2, 7, 3));
+        assert_eq!((memory[1], memory[2500]), (7, 7));
+        assert_ne!(memory, clone);
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut memory = Memory::<i64>::from(vec![1, 2, 3]);
+        memory.set_limit(100);
+        assert_eq!(
+            memory.set(100, 1),
+            Err(MemoryLimitExceeded {
+                address: 100,
+                limit: 100
+            })
+        );
+        assert!(memory.get(100).is_err());
+        assert!(memory.set(99, 1).is_ok());
+    }
+
+    #[test]
+    fn test_far_writes() {
+        let mut memory = Memory::<i64>::from(vec![1, 2, 3]);
+        memory.set_limit(usize::MAX);
+        memory.set(usize::MAX - 1, 4).unwrap();
+        memory.set(1 << 40, 5).unwrap();
+        let mut clone = memory.clone();
+        assert_eq!(clone.pages_touched(), 2);
+        assert_eq!((clone[usize::MAX - 1], clone[1 << 40]), (4, 5));
+        assert_eq!(clone, memory);
+
+        clone.set(1 << 40, 6).unwrap();
+        assert_eq!((memory[1 << 40], clone[1 << 40]), (5, 6));
+        assert_eq!(
+            clone.iter().collect::<Vec<_>>(),
+            vec![(0, 1), (1, 2), (2, 3), (1 << 40, 6), (usize::MAX - 1, 4)]
+        );
+    }
+}
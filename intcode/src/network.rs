@@ -0,0 +1,522 @@
+// The impls generated by `err-derive` live inside an anonymous const.
+#![allow(non_local_definitions)]
+
+use crate::{
+    error::Error,
+    machine::{Event, Machine},
+    word::Word,
+};
+
+use err_derive::Error;
+use std::{
+    fmt,
+    ops::Index,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc, Arc, OnceLock,
+    },
+    thread,
+};
+
+/// A machine added to a [`Network`].
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy)]
+pub struct MachineId(usize);
+
+impl MachineId {
+    /// The position of the machine in the order they were added.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+impl fmt::Display for MachineId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Why a network stopped before every machine halted.
+#[derive(Debug, Error, PartialEq, Eq, Clone)]
+pub enum NetworkError {
+    #[error(display = "machine {} failed: {}", machine, error)]
+    Machine { machine: MachineId, error: Error },
+    /// Every machine that has not halted is waiting for input no one can send.
+    #[error(display = "deadlock, machines {:?} wait for input", waiting)]
+    Deadlock { waiting: Vec<MachineId> },
+    /// `from` cannot be reached from `to` through the pipes of the network.
+    #[error(display = "feedback from {} to {} closes no loop", from, to)]
+    NoLoop { from: MachineId, to: MachineId },
+}
+
+/// Everything each machine output while the network ran, by machine.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Outputs<W>(Vec<Vec<W>>);
+
+impl<W> Index<MachineId> for Outputs<W> {
+    type Output = [W];
+
+    fn index(&self, id: MachineId) -> &[W] {
+        &self.0[id.0]
+    }
+}
+
+/// Counts a value as in flight from when it is sent until it is dropped.
+struct Token(Arc<AtomicUsize>);
+
+impl Token {
+    fn new(active: &Arc<AtomicUsize>) -> Self {
+        active.fetch_add(1, Ordering::SeqCst);
+        Self(active.clone())
+    }
+}
+
+impl Drop for Token {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// What is sent down the channel into a machine.
+enum Message<W> {
+    Value(W, Token),
+    /// The network has failed, so the machine should stop too.
+    Stop,
+}
+
+/// The sending end of the channel into a machine.
+trait Inbox<W>: Send + Sync + 'static {
+    /// Messages to a machine that has stopped are dropped.
+    fn send(&self, message: Message<W>);
+}
+
+impl<W: Word> Inbox<W> for mpsc::Sender<Message<W>> {
+    fn send(&self, message: Message<W>) {
+        mpsc::Sender::send(self, message).ok();
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<W: Word> Inbox<W> for tokio::sync::mpsc::UnboundedSender<Message<W>> {
+    fn send(&self, message: Message<W>) {
+        tokio::sync::mpsc::UnboundedSender::send(self, message).ok();
+    }
+}
+
+/// The state shared by the machines of a running network.
+struct Shared<S> {
+    inboxes: Vec<S>,
+    /// How many machines are running, plus how many values have been sent
+    /// and not yet taken. The network is deadlocked once this drops to
+    /// zero while any machine waits.
+    active: Arc<AtomicUsize>,
+    waiting: Vec<AtomicBool>,
+    error: OnceLock<NetworkError>,
+}
+
+impl<S> Shared<S> {
+    /// Fails the network, unless it already has, and stops every machine.
+    fn fail<W>(&self, error: NetworkError)
+    where
+        S: Inbox<W>,
+    {
+        if self.error.set(error).is_ok() {
+            for inbox in &self.inboxes {
+                inbox.send(Message::Stop);
+            }
+        }
+    }
+
+    /// Counts one machine less as running, failing the network if none are
+    /// left while some still wait for input.
+    fn idle<W>(&self)
+    where
+        S: Inbox<W>,
+    {
+        if self.active.fetch_sub(1, Ordering::SeqCst) != 1 {
+            return;
+        }
+        let waiting: Vec<_> = (0..self.waiting.len())
+            .filter(|&n| self.waiting[n].load(Ordering::SeqCst))
+            .map(MachineId)
+            .collect();
+        if !waiting.is_empty() {
+            self.fail(NetworkError::Deadlock { waiting });
+        }
+    }
+}
+
+/// A machine of a running network, driven by a thread or a task that owns
+/// the receiving end of its channel.
+struct Node<W, S> {
+    id: usize,
+    machine: Machine<W>,
+    links: Vec<usize>,
+    outputs: Vec<W>,
+    shared: Arc<Shared<S>>,
+}
+
+impl<W: Word, S: Inbox<W>> Node<W, S> {
+    /// Runs the machine until it stops, or needs input that `poll` does not
+    /// have yet. In the latter case the machine is marked as waiting, and
+    /// [`Node::wake`] must be given the next message that arrives.
+    fn run(&mut self, mut poll: impl FnMut() -> Option<Message<W>>) -> Option<Result<(), Error>> {
+        loop {
+            if self.shared.error.get().is_some() {
+                return Some(Ok(()));
+            }
+            match self.machine.run_until_event() {
+                Ok(Event::Output(value)) => {
+                    self.outputs.push(value);
+                    for &to in &self.links {
+                        let token = Token::new(&self.shared.active);
+                        self.shared.inboxes[to].send(Message::Value(value, token));
+                    }
+                }
+                Ok(Event::NeedsInput) => match poll() {
+                    Some(message) => self.take(message),
+                    None => {
+                        self.shared.waiting[self.id].store(true, Ordering::SeqCst);
+                        self.shared.idle();
+                        return None;
+                    }
+                },
+                Ok(Event::Halted) => return Some(Ok(())),
+                Err(err) => return Some(Err(err)),
+            }
+        }
+    }
+
+    fn wake(&mut self, message: Message<W>) {
+        self.shared.waiting[self.id].store(false, Ordering::SeqCst);
+        // Counted before the token of the value is dropped, so the count
+        // never passes zero while this machine still has work.
+        self.shared.active.fetch_add(1, Ordering::SeqCst);
+        self.take(message);
+    }
+
+    fn take(&mut self, message: Message<W>) {
+        if let Message::Value(value, _token) = message {
+            self.machine.push_input(value);
+        }
+    }
+
+    /// Marks the machine as stopped, once the receiving end of its channel
+    /// has been dropped along with any values still in it.
+    fn stop(self, result: Result<(), Error>) -> Vec<W> {
+        if let Err(error) = result {
+            self.shared.fail(NetworkError::Machine {
+                machine: MachineId(self.id),
+                error,
+            });
+        }
+        self.shared.idle();
+        self.outputs
+    }
+}
+
+/// Machines connected output to input, each running as its own task.
+///
+/// Every value a machine outputs is sent down a channel to each machine it
+/// is piped to, and recorded in the [`Outputs`] of the run. A machine
+/// blocks until a value arrives when it needs input. The run fails with
+/// [`NetworkError::Deadlock`] once every machine that has not halted waits
+/// for input that can never come.
+///
+/// [`Network::run`] runs every machine on a thread of its own, while
+/// `Network::run_async` runs them as tokio tasks when the `tokio` feature
+/// is enabled.
+#[derive(Debug, Clone)]
+pub struct Network<W = i64> {
+    machines: Vec<Machine<W>>,
+    /// The machines each machine sends its output to.
+    links: Vec<Vec<usize>>,
+    /// The pipes added with [`Network::feedback`], checked before a run.
+    feedback: Vec<(MachineId, MachineId)>,
+}
+
+impl<W: Word> Default for Network<W> {
+    fn default() -> Self {
+        Self {
+            machines: Vec::new(),
+            links: Vec::new(),
+            feedback: Vec::new(),
+        }
+    }
+}
+
+impl<W: Word> Network<W> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a machine, keeping any input already pushed to it.
+    pub fn add(&mut self, machine: Machine<W>) -> MachineId {
+        self.machines.push(machine);
+        self.links.push(Vec::new());
+        MachineId(self.machines.len() - 1)
+    }
+
+    /// Queues a value for the input of machine `id`.
+    pub fn push_input(&mut self, id: MachineId, value: W) -> &mut Self {
+        self.machines[id.0].push_input(value);
+        self
+    }
+
+    /// Sends the output of `from` to the input of `to`.
+    pub fn pipe(&mut self, from: MachineId, to: MachineId) -> &mut Self {
+        self.links[from.0].push(to.0);
+        self
+    }
+
+    /// Sends the output of `from` back to the input of `to`, closing a loop
+    /// of pipes. A run fails with [`NetworkError::NoLoop`] if `from` cannot
+    /// be reached from `to`.
+    pub fn feedback(&mut self, from: MachineId, to: MachineId) -> &mut Self {
+        self.feedback.push((from, to));
+        self.pipe(from, to)
+    }
+
+    fn check_topology(&self) -> Result<(), NetworkError> {
+        for &(from, to) in &self.feedback {
+            let mut seen = vec![false; self.machines.len()];
+            let mut work = vec![to.0];
+            while let Some(n) = work.pop() {
+                if !seen[n] {
+                    seen[n] = true;
+                    work.extend(&self.links[n]);
+                }
+            }
+            if !seen[from.0] {
+                return Err(NetworkError::NoLoop { from, to });
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the topology and sets up a node for every machine, sending to
+    /// `inboxes`.
+    fn start<S: Inbox<W>>(self, inboxes: Vec<S>) -> Result<Vec<Node<W, S>>, NetworkError> {
+        self.check_topology()?;
+        let count = self.machines.len();
+        let shared = Arc::new(Shared {
+            inboxes,
+            active: Arc::new(AtomicUsize::new(count)),
+            waiting: (0..count).map(|_| AtomicBool::new(false)).collect(),
+            error: OnceLock::new(),
+        });
+        let nodes = self
+            .machines
+            .into_iter()
+            .zip(self.links)
+            .enumerate()
+            .map(|(id, (machine, links))| Node {
+                id,
+                machine,
+                links,
+                outputs: Vec::new(),
+                shared: shared.clone(),
+            })
+            .collect();
+        Ok(nodes)
+    }
+
+    /// Runs every machine on its own thread until all have halted.
+    pub fn run(self) -> Result<Outputs<W>, NetworkError> {
+        let (senders, receivers): (Vec<_>, Vec<_>) =
+            (0..self.machines.len()).map(|_| mpsc::channel()).unzip();
+        let nodes = self.start(senders)?;
+        let shared = nodes.first().map(|node| node.shared.clone());
+        let threads: Vec<_> = nodes
+            .into_iter()
+            .zip(receivers)
+            .map(|(mut node, inbox)| {
+                thread::spawn(move || {
+                    let result = loop {
+                        if let Some(result) = node.run(|| inbox.try_recv().ok()) {
+                            break result;
+                        }
+                        // The network keeps a sender to every machine.
+                        node.wake(inbox.recv().unwrap());
+                    };
+                    drop(inbox);
+                    node.stop(result)
+                })
+            })
+            .collect();
+        let outputs = threads
+            .into_iter()
+            .map(|thread| thread.join().expect("network machine panicked"))
+            .collect();
+        finish(shared, outputs)
+    }
+}
+
+fn finish<W, S>(
+    shared: Option<Arc<Shared<S>>>,
+    outputs: Vec<Vec<W>>,
+) -> Result<Outputs<W>, NetworkError> {
+    match shared.and_then(|shared| shared.error.get().cloned()) {
+        Some(error) => Err(error),
+        None => Ok(Outputs(outputs)),
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<W: Word> Network<W> {
+    /// Runs every machine as a tokio task until all have halted.
+    ///
+    /// Machines only yield while they wait for input, so a machine that
+    /// computes for long without needing any holds on to its worker thread.
+    pub async fn run_async(self) -> Result<Outputs<W>, NetworkError> {
+        use tokio::sync::mpsc::unbounded_channel;
+
+        let (senders, receivers): (Vec<_>, Vec<_>) = (0..self.machines.len())
+            .map(|_| unbounded_channel())
+            .unzip();
+        let nodes = self.start(senders)?;
+        let shared = nodes.first().map(|node| node.shared.clone());
+        let tasks: Vec<_> = nodes
+            .into_iter()
+            .zip(receivers)
+            .map(|(mut node, mut inbox)| {
+                tokio::spawn(async move {
+                    let result = loop {
+                        if let Some(result) = node.run(|| inbox.try_recv().ok()) {
+                            break result;
+                        }
+                        // The network keeps a sender to every machine.
+                        node.wake(inbox.recv().await.unwrap());
+                    };
+                    drop(inbox);
+                    node.stop(result)
+                })
+            })
+            .collect();
+        let mut outputs = Vec::new();
+        for task in tasks {
+            outputs.push(task.await.expect("network machine panicked"));
+        }
+        finish(shared, outputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm::assemble, error::ErrorKind};
+
+    /// Five amplifiers in a row, fed back into the first one if `feedback`.
+    fn amplifiers(program: &[i64], phases: &[i64], feedback: bool) -> (Network, MachineId) {
+        let mut network = Network::new();
+        let ids: Vec<_> = phases
+            .iter()
+            .map(|&phase| {
+                let mut machine = Machine::new(program.to_vec());
+                machine.push_input(phase);
+                network.add(machine)
+            })
+            .collect();
+        network.push_input(ids[0], 0);
+        for pair in ids.windows(2) {
+            network.pipe(pair[0], pair[1]);
+        }
+        let last = *ids.last().unwrap();
+        if feedback {
+            network.feedback(last, ids[0]);
+        }
+        (network, last)
+    }
+
+    const LINEAR: [i64; 17] = [
+        3, 15, 3, 16, 1002, 16, 10, 16, 1, 16, 15, 15, 4, 15, 99, 0, 0,
+    ];
+    const FEEDBACK: [i64; 29] = [
+        3, 26, 1001, 26, -4, 26, 3, 27, 1002, 27, 2, 27, 1, 27, 26, 27, 4, 27, 1001, 28, -1, 28,
+        1005, 28, 6, 99, 0, 0, 5,
+    ];
+
+    #[test]
+    fn test_amplifiers() {
+        let (network, last) = amplifiers(&LINEAR, &[4, 3, 2, 1, 0], false);
+        assert_eq!(network.run().unwrap()[last], [43210]);
+        let (network, last) = amplifiers(&FEEDBACK, &[9, 8, 7, 6, 5], true);
+        assert_eq!(network.run().unwrap()[last].last(), Some(&139629729));
+    }
+
+    #[test]
+    fn test_deadlock() {
+        // Both wait for the other to go first.
+        let echo = assemble("loop: in -> [v]\nout [v]\njnz #1, loop\nv: .data 0").unwrap();
+        let mut network = Network::new();
+        let a = network.add(Machine::new(echo.clone()));
+        let b = network.add(Machine::new(echo));
+        network.pipe(a, b).feedback(b, a);
+        assert_eq!(
+            network.clone().run(),
+            Err(NetworkError::Deadlock {
+                waiting: vec![a, b]
+            })
+        );
+
+        // Counts down through both until the first halts, leaving the second
+        // waiting.
+        let mut network = Network::new();
+        let countdown = "loop: in -> [v]\nout [v]\njnz [v], loop\nhlt\nv: .data 0";
+        let decrement = "loop: in -> [v]\nadd [v], #-1 -> [v]\nout [v]\njnz #1, loop\nv: .data 0";
+        let a = network.add(Machine::new(assemble(countdown).unwrap()));
+        let b = network.add(Machine::new(assemble(decrement).unwrap()));
+        network.push_input(a, 3).pipe(a, b).feedback(b, a);
+        match network.run() {
+            Err(NetworkError::Deadlock { waiting }) => assert_eq!(waiting, vec![b]),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_failure() {
+        let mut network = Network::new();
+        let a = network.add(Machine::new(vec![104, 1, 55]));
+        let b = network.add(Machine::new(vec![3, 0, 3, 0, 99]));
+        network.pipe(a, b);
+        match network.run() {
+            Err(NetworkError::Machine { machine, error }) => {
+                assert_eq!((machine, error.kind), (a, ErrorKind::UnknownOpcode))
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_feedback_without_loop() {
+        let mut network: Network = Network::new();
+        let a = network.add(Machine::new(vec![99]));
+        let b = network.add(Machine::new(vec![99]));
+        network.feedback(b, a);
+        let error = network.run().unwrap_err();
+        assert_eq!(error, NetworkError::NoLoop { from: b, to: a });
+        assert_eq!(error.to_string(), "feedback from #1 to #0 closes no loop");
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_async() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let (network, last) = amplifiers(&FEEDBACK, &[9, 8, 7, 6, 5], true);
+        let outputs = runtime.block_on(network.run_async()).unwrap();
+        assert_eq!(outputs[last].last(), Some(&139629729));
+
+        let echo = assemble("loop: in -> [v]\nout [v]\njnz #1, loop\nv: .data 0").unwrap();
+        let mut network = Network::new();
+        let a = network.add(Machine::new(echo.clone()));
+        let b = network.add(Machine::new(echo));
+        network.pipe(a, b).feedback(b, a);
+        assert_eq!(
+            runtime.block_on(network.run_async()),
+            Err(NetworkError::Deadlock {
+                waiting: vec![a, b]
+            })
+        );
+    }
+}
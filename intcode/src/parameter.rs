@@ -0,0 +1,365 @@
+use crate::{
+    error::ErrorKind,
+    io::{Input, Output},
+    machine::Status,
+    memory::Memory,
+    word::Word,
+};
+
+use serde::{Deserialize, Serialize};
+use std::convert::{TryFrom, TryInto};
+
+/// How the argument of an instruction is to be interpreted.
+#[derive(Debug, Eq, PartialEq, Clone, Copy, Default, Serialize, Deserialize)]
+pub enum ParameterMode {
+    /// The argument is the address of the value.
+    #[default]
+    #[serde(rename = "position")]
+    PositionMode,
+    /// The argument is the value itself.
+    #[serde(rename = "immediate")]
+    ImmediateMode,
+    /// The argument is an address relative to the relative base.
+    #[serde(rename = "relative")]
+    RelativeMode,
+}
+
+impl TryFrom<usize> for ParameterMode {
+    type Error = ErrorKind;
+
+    fn try_from(num: usize) -> Result<Self, Self::Error> {
+        match num {
+            0 => Ok(ParameterMode::PositionMode),
+            1 => Ok(ParameterMode::ImmediateMode),
+            2 => Ok(ParameterMode::RelativeMode),
+            n => Err(ErrorKind::InvalidParameterMode(n)),
+        }
+    }
+}
+
+impl From<ParameterMode> for i64 {
+    fn from(mode: ParameterMode) -> Self {
+        match mode {
+            ParameterMode::PositionMode => 0,
+            ParameterMode::ImmediateMode => 1,
+            ParameterMode::RelativeMode => 2,
+        }
+    }
+}
+
+/// Converts a value used as an address, rejecting negative ones and ones
+/// too large for `usize`.
+pub(crate) fn address<W: Word>(value: W) -> Result<usize, ErrorKind> {
+    let value = value.to_i128();
+    match usize::try_from(value) {
+        Ok(address) => Ok(address),
+        Err(_) if value < 0 => Err(ErrorKind::NegativeAddress(value)),
+        Err(_) => Err(ErrorKind::AddressOutOfRange(value)),
+    }
+}
+
+/// The address `n` cells past `ip`, which is past the memory limit if it
+/// does not fit in `usize`.
+fn offset(ip: usize, n: usize, limit: usize) -> Result<usize, ErrorKind> {
+    ip.checked_add(n).ok_or(ErrorKind::MemoryLimit {
+        address: usize::MAX,
+        limit,
+    })
+}
+
+impl ParameterMode {
+    /// The address the argument stored at `ip` refers to.
+    fn address<W: Word>(
+        self,
+        ip: usize,
+        code: &Memory<W>,
+        relative_base: W,
+    ) -> Result<usize, ErrorKind> {
+        match self {
+            ParameterMode::PositionMode => address(code.get(ip)?),
+            ParameterMode::ImmediateMode => Err(ErrorKind::WriteInImmediateMode),
+            ParameterMode::RelativeMode => address(
+                relative_base
+                    .checked_add(code.get(ip)?)
+                    .ok_or(ErrorKind::ArithmeticOverflow)?,
+            ),
+        }
+    }
+
+    /// Reads the argument stored at `ip`.
+    pub fn get_ip<W: Word>(
+        self,
+        ip: usize,
+        code: &Memory<W>,
+        relative_base: W,
+    ) -> Result<W, ErrorKind> {
+        match self {
+            ParameterMode::ImmediateMode => Ok(code.get(ip)?),
+            _ => Ok(code.get(self.address(ip, code, relative_base)?)?),
+        }
+    }
+
+    /// Writes `value` to the argument stored at `ip`.
+    pub fn set_ip<W: Word>(
+        self,
+        ip: usize,
+        code: &mut Memory<W>,
+        relative_base: W,
+        value: W,
+    ) -> Result<(), ErrorKind> {
+        Ok(code.set(self.address(ip, code, relative_base)?, value)?)
+    }
+}
+
+/// A decoded instruction word.
+///
+/// `c`, `b` and `a` are the modes of the first, second and third argument.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub struct Parameter {
+    pub opcode: usize,
+    pub a: ParameterMode,
+    pub b: ParameterMode,
+    pub c: ParameterMode,
+}
+
+impl Parameter {
+    /// The short name of the opcode, `None` if the opcode is unknown.
+    pub fn mnemonic(self) -> Option<&'static str> {
+        match self.opcode {
+            1 => Some("ADD"),
+            2 => Some("MUL"),
+            3 => Some("IN"),
+            4 => Some("OUT"),
+            5 => Some("JNZ"),
+            6 => Some("JZ"),
+            7 => Some("LT"),
+            8 => Some("EQ"),
+            9 => Some("ARB"),
+            99 => Some("HLT"),
+            _ => None,
+        }
+    }
+
+    /// How many arguments follow the instruction word.
+    pub fn arg_count(self) -> Option<usize> {
+        match self.opcode {
+            1 | 2 | 7 | 8 => Some(3),
+            5 | 6 => Some(2),
+            3 | 4 | 9 => Some(1),
+            99 => Some(0),
+            _ => None,
+        }
+    }
+
+    /// Whether the last argument is an address that is written to.
+    pub fn writes(self) -> bool {
+        matches!(self.opcode, 1 | 2 | 3 | 7 | 8)
+    }
+
+    /// The address the instruction at `ip` is about to write to, if any.
+    pub fn write_address<W: Word>(
+        self,
+        ip: usize,
+        code: &Memory<W>,
+        relative_base: W,
+    ) -> Option<usize> {
+        let count = self.arg_count()?;
+        match self.writes() {
+            true => self.modes()[count - 1]
+                .address(ip.checked_add(count)?, code, relative_base)
+                .ok(),
+            false => None,
+        }
+    }
+
+    /// The instruction word this decodes from.
+    pub fn encode(self) -> i64 {
+        self.opcode as i64
+            + 100 * i64::from(self.c)
+            + 1000 * i64::from(self.b)
+            + 10000 * i64::from(self.a)
+    }
+
+    /// The modes of the arguments, in the order they follow the instruction.
+    pub fn modes(self) -> [ParameterMode; 3] {
+        [self.c, self.b, self.a]
+    }
+
+    /// Executes the instruction at `ip`, moving `ip` to the next instruction.
+    pub fn run<W: Word, I: Input<W> + ?Sized, O: Output<W> + ?Sized>(
+        self,
+        ip: &mut usize,
+        relative_base: &mut W,
+        code: &mut Memory<W>,
+        inn: &mut I,
+        out: &mut O,
+    ) -> Result<Status, ErrorKind> {
+        let (at, limit) = (*ip, code.limit());
+        let arg = |n| offset(at, n, limit);
+        match self.opcode {
+            1 => {
+                // pluss
+                self.a.set_ip(
+                    arg(3)?,
+                    code,
+                    *relative_base,
+                    self.c
+                        .get_ip(arg(1)?, code, *relative_base)?
+                        .checked_add(self.b.get_ip(arg(2)?, code, *relative_base)?)
+                        .ok_or(ErrorKind::ArithmeticOverflow)?,
+                )?;
+                *ip = arg(4)?;
+                Ok(Status::Running)
+            }
+            2 => {
+                // multiply
+                self.a.set_ip(
+                    arg(3)?,
+                    code,
+                    *relative_base,
+                    self.c
+                        .get_ip(arg(1)?, code, *relative_base)?
+                        .checked_mul(self.b.get_ip(arg(2)?, code, *relative_base)?)
+                        .ok_or(ErrorKind::ArithmeticOverflow)?,
+                )?;
+                *ip = arg(4)?;
+                Ok(Status::Running)
+            }
+            3 => {
+                // read input
+                match inn.read_value()? {
+                    Some(n) => {
+                        self.c.set_ip(arg(1)?, code, *relative_base, n)?;
+                        *ip = arg(2)?;
+                        Ok(Status::Running)
+                    }
+                    None => Ok(Status::Blocking),
+                }
+            }
+            4 => {
+                // write output
+                out.write_value(self.c.get_ip(arg(1)?, code, *relative_base)?)?;
+                *ip = arg(2)?;
+                Ok(Status::Running)
+            }
+            5 => {
+                // jmp if not 0
+                match self.c.get_ip(arg(1)?, code, *relative_base)? == *W::ZERO {
+                    true => *ip = arg(3)?,
+                    false => *ip = address(self.b.get_ip(arg(2)?, code, *relative_base)?)?,
+                };
+                Ok(Status::Running)
+            }
+            6 => {
+                // jump if 0
+                match self.c.get_ip(arg(1)?, code, *relative_base)? == *W::ZERO {
+                    true => *ip = address(self.b.get_ip(arg(2)?, code, *relative_base)?)?,
+                    false => *ip = arg(3)?,
+                }
+                Ok(Status::Running)
+            }
+            7 => {
+                // cmp lt
+                self.a.set_ip(
+                    arg(3)?,
+                    code,
+                    *relative_base,
+                    W::from_i64(
+                        (self.c.get_ip(arg(1)?, code, *relative_base)?
+                            < self.b.get_ip(arg(2)?, code, *relative_base)?)
+                            as i64,
+                    ),
+                )?;
+                *ip = arg(4)?;
+                Ok(Status::Running)
+            }
+            8 => {
+                // cmp equals
+                self.a.set_ip(
+                    arg(3)?,
+                    code,
+                    *relative_base,
+                    W::from_i64(
+                        (self.c.get_ip(arg(1)?, code, *relative_base)?
+                            == self.b.get_ip(arg(2)?, code, *relative_base)?)
+                            as i64,
+                    ),
+                )?;
+                *ip = arg(4)?;
+                Ok(Status::Running)
+            }
+            9 => {
+                // adjust relative base
+                *relative_base = relative_base
+                    .checked_add(self.c.get_ip(arg(1)?, code, *relative_base)?)
+                    .ok_or(ErrorKind::ArithmeticOverflow)?;
+                *ip = arg(2)?;
+                Ok(Status::Running)
+            }
+            99 => Ok(Status::Halted),
+            _ => Err(ErrorKind::UnknownOpcode),
+        }
+    }
+}
+
+fn mod_and_divide(num: &mut usize, mod_by: usize) -> usize {
+    let ans = *num % mod_by;
+    *num /= mod_by;
+    ans
+}
+
+impl TryFrom<i64> for Parameter {
+    type Error = ErrorKind;
+
+    fn try_from(input: i64) -> Result<Self, Self::Error> {
+        let mut input: usize = input.try_into().map_err(|_| ErrorKind::UnknownOpcode)?;
+        let opcode = mod_and_divide(&mut input, 100);
+        let c = mod_and_divide(&mut input, 10).try_into()?;
+        let b = mod_and_divide(&mut input, 10).try_into()?;
+        let a = mod_and_divide(&mut input, 10).try_into()?;
+        Ok(Self { opcode, a, b, c })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let param = Parameter::try_from(1002).unwrap();
+        assert_eq!(param.opcode, 2);
+        assert_eq!(param.c, ParameterMode::PositionMode);
+        assert_eq!(param.b, ParameterMode::ImmediateMode);
+        assert_eq!(param.a, ParameterMode::PositionMode);
+        let param = Parameter::try_from(21209).unwrap();
+        assert_eq!(param.opcode, 9);
+        assert_eq!(param.c, ParameterMode::RelativeMode);
+        assert_eq!(param.b, ParameterMode::ImmediateMode);
+        assert_eq!(param.a, ParameterMode::RelativeMode);
+        assert_eq!(
+            Parameter::try_from(301),
+            Err(ErrorKind::InvalidParameterMode(3))
+        );
+        assert_eq!(Parameter::try_from(-1), Err(ErrorKind::UnknownOpcode));
+    }
+
+    #[test]
+    fn test_shape() {
+        let param = Parameter::try_from(1107).unwrap();
+        assert_eq!(param.mnemonic(), Some("LT"));
+        assert_eq!(param.arg_count(), Some(3));
+        assert!(param.writes());
+        let param = Parameter::try_from(1005).unwrap();
+        assert_eq!(param.arg_count(), Some(2));
+        assert!(!param.writes());
+        assert_eq!(Parameter::try_from(21107).unwrap().encode(), 21107);
+        let code = Memory::<i64>::from(vec![21107, 1, 2, -3]);
+        let param = Parameter::try_from(21107).unwrap();
+        assert_eq!(param.write_address(0, &code, 10), Some(7));
+        assert_eq!(param.write_address(0, &code, 0), None);
+        let param = Parameter::try_from(42).unwrap();
+        assert_eq!(param.mnemonic(), None);
+        assert_eq!(param.arg_count(), None);
+    }
+}
@@ -0,0 +1,439 @@
+//! Symbolic evaluation of programs that only add and multiply, such as
+//! day 2's, into polynomials.
+//!
+//! This is separate from the [`Expr`](crate::Expr) engine of
+//! [`SymbolicExecutor`](crate::SymbolicExecutor) because the two answer
+//! different questions. The unknowns of day 2 are memory cells patched
+//! before the run, not values read by opcode 3, and solving for them needs
+//! the result in a normal form whose coefficients can be read off. `Expr`
+//! only folds constants, so the same value can be many different trees,
+//! and the executor finds inputs by trying every combination in its search
+//! range, which is the brute force this module exists to avoid.
+
+// The impls generated by `err-derive` live inside an anonymous const.
+#![allow(non_local_definitions)]
+
+use crate::{
+    memory::DEFAULT_MEMORY_LIMIT,
+    parameter::{Parameter, ParameterMode},
+};
+
+use err_derive::Error;
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    fmt::{self, Write},
+    ops::RangeInclusive,
+};
+
+/// A polynomial with integer coefficients in the unknowns `x0`, `x1`, ...
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Polynomial {
+    /// The coefficient of every term by the exponent of each unknown, with
+    /// no trailing zero exponents and no zero coefficients.
+    terms: BTreeMap<Vec<u32>, i64>,
+}
+
+impl Polynomial {
+    pub fn constant(value: i64) -> Self {
+        let mut terms = BTreeMap::new();
+        if value != 0 {
+            terms.insert(Vec::new(), value);
+        }
+        Self { terms }
+    }
+
+    /// The unknown `x{index}`.
+    pub fn unknown(index: usize) -> Self {
+        let mut exponents = vec![0; index + 1];
+        exponents[index] = 1;
+        Self {
+            terms: vec![(exponents, 1)].into_iter().collect(),
+        }
+    }
+
+    /// The value, if it does not depend on any unknown.
+    pub fn as_constant(&self) -> Option<i64> {
+        match self.terms.iter().next() {
+            None => Some(0),
+            Some((exponents, &value)) if exponents.is_empty() && self.terms.len() == 1 => {
+                Some(value)
+            }
+            Some(_) => None,
+        }
+    }
+
+    /// The highest sum of exponents of any term, 0 for the zero polynomial.
+    pub fn degree(&self) -> u32 {
+        let degree = |exponents: &Vec<u32>| exponents.iter().sum();
+        self.terms.keys().map(degree).max().unwrap_or(0)
+    }
+
+    /// `None` if a coefficient overflows.
+    pub fn checked_add(&self, other: &Self) -> Option<Self> {
+        let mut terms = self.terms.clone();
+        for (exponents, &value) in &other.terms {
+            let sum = terms.get(exponents).unwrap_or(&0).checked_add(value)?;
+            match sum {
+                0 => terms.remove(exponents),
+                _ => terms.insert(exponents.clone(), sum),
+            };
+        }
+        Some(Self { terms })
+    }
+
+    /// `None` if a coefficient overflows.
+    pub fn checked_mul(&self, other: &Self) -> Option<Self> {
+        let mut product = Self::default();
+        for (a, &x) in &self.terms {
+            for (b, &y) in &other.terms {
+                let mut exponents = vec![0; a.len().max(b.len())];
+                for (n, &e) in a.iter().enumerate() {
+                    exponents[n] += e;
+                }
+                for (n, &e) in b.iter().enumerate() {
+                    exponents[n] += e;
+                }
+                let term = Self {
+                    terms: vec![(exponents, x.checked_mul(y)?)].into_iter().collect(),
+                };
+                product = product.checked_add(&term)?;
+            }
+        }
+        Some(product)
+    }
+
+    /// The value for the given values of the unknowns, `None` if it
+    /// overflows. Unknowns without a value count as 0.
+    pub fn evaluate(&self, values: &[i64]) -> Option<i64> {
+        self.terms
+            .iter()
+            .try_fold(0_i64, |sum, (exponents, &value)| {
+                let term = exponents
+                    .iter()
+                    .enumerate()
+                    .try_fold(value, |term, (n, &e)| {
+                        let x = values.get(n).copied().unwrap_or(0);
+                        term.checked_mul(x.checked_pow(e)?)
+                    })?;
+                sum.checked_add(term)
+            })
+    }
+
+    /// The polynomial as a constant plus a coefficient for each of the first
+    /// `unknowns` unknowns, if it has no term of higher degree or in other
+    /// unknowns.
+    pub fn linear(&self, unknowns: usize) -> Option<Linear> {
+        let mut linear = Linear {
+            constant: 0,
+            coefficients: vec![0; unknowns],
+        };
+        for (exponents, &value) in &self.terms {
+            match (exponents.len(), exponents.iter().sum::<u32>()) {
+                (0, _) => linear.constant = value,
+                (n, 1) if n <= unknowns => linear.coefficients[n - 1] = value,
+                _ => return None,
+            }
+        }
+        Some(linear)
+    }
+
+    /// Writes the polynomial with the unknowns given `names`, or `x0`, `x1`,
+    /// ... past the end of them.
+    pub fn format(&self, names: &[&str]) -> String {
+        let mut text = String::new();
+        // Highest degree first, the constant last.
+        let mut terms: Vec<_> = self.terms.iter().collect();
+        terms.sort_by_key(|(exponents, _)| {
+            std::cmp::Reverse((exponents.iter().sum::<u32>(), exponents.to_vec()))
+        });
+        for (n, (exponents, &value)) in terms.into_iter().enumerate() {
+            let sign = match (n, value < 0) {
+                (0, true) => "-",
+                (0, false) => "",
+                (_, true) => " - ",
+                (_, false) => " + ",
+            };
+            text.push_str(sign);
+            let mut factors = Vec::new();
+            if value.unsigned_abs() != 1 || exponents.is_empty() {
+                factors.push(value.unsigned_abs().to_string());
+            }
+            for (n, &e) in exponents.iter().enumerate() {
+                let name = match names.get(n) {
+                    Some(name) => name.to_string(),
+                    None => format!("x{}", n),
+                };
+                match e {
+                    0 => (),
+                    1 => factors.push(name),
+                    _ => factors.push(format!("{}^{}", name, e)),
+                }
+            }
+            write!(text, "{}", factors.join("*")).unwrap();
+        }
+        match text.is_empty() {
+            true => "0".to_string(),
+            false => text,
+        }
+    }
+}
+
+impl fmt::Display for Polynomial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format(&[]))
+    }
+}
+
+/// A polynomial of degree at most 1, see [`Polynomial::linear`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Linear {
+    pub constant: i64,
+    pub coefficients: Vec<i64>,
+}
+
+impl Linear {
+    /// The first values of the unknowns within `ranges` that give `target`,
+    /// in the order of nested loops over the ranges.
+    ///
+    /// Every unknown but the last one with a coefficient is looped over,
+    /// and that one is solved for directly. `None` as well unless there is
+    /// one range per unknown.
+    pub fn solve(&self, target: i64, ranges: &[RangeInclusive<i64>]) -> Option<Vec<i64>> {
+        if ranges.len() != self.coefficients.len() {
+            return None;
+        }
+        let solved = self.coefficients.iter().rposition(|&c| c != 0);
+        if ranges.iter().any(RangeInclusive::is_empty) {
+            return None;
+        }
+        let mut values: Vec<i64> = ranges.iter().map(|range| *range.start()).collect();
+        loop {
+            let sum: i128 = values
+                .iter()
+                .zip(&self.coefficients)
+                .enumerate()
+                .filter(|&(n, _)| Some(n) != solved)
+                .map(|(_, (&x, &c))| x as i128 * c as i128)
+                .sum();
+            let rest = target as i128 - self.constant as i128 - sum;
+            match solved {
+                None if rest == 0 => return Some(values),
+                None => (),
+                Some(n) => {
+                    let c = self.coefficients[n] as i128;
+                    if rest % c == 0
+                        && i64::try_from(rest / c).is_ok_and(|x| ranges[n].contains(&x))
+                    {
+                        values[n] = (rest / c) as i64;
+                        return Some(values);
+                    }
+                }
+            }
+            // Advances the last unknown that is looped over, like an odometer.
+            let mut n = values.len();
+            loop {
+                n = n.checked_sub(1)?;
+                if Some(n) == solved {
+                    continue;
+                }
+                match values[n] < *ranges[n].end() {
+                    true => {
+                        values[n] += 1;
+                        break;
+                    }
+                    false => values[n] = *ranges[n].start(),
+                }
+            }
+        }
+    }
+}
+
+/// Why a program could not be evaluated symbolically.
+#[derive(Debug, Error, PartialEq, Eq, Clone)]
+pub enum SymbolicError {
+    #[error(display = "{:04}: {} is not an add, multiply or halt", address, word)]
+    Unsupported { address: usize, word: i64 },
+    #[error(display = "{:04}: the instruction depends on an unknown", _0)]
+    UnknownInstruction(usize),
+    #[error(display = "{:04}: writes to an address that depends on an unknown", _0)]
+    UnknownWrite(usize),
+    #[error(display = "{:04}: negative address", _0)]
+    NegativeAddress(usize),
+    #[error(display = "{:04}: a coefficient overflows", _0)]
+    Overflow(usize),
+    #[error(display = "{:04}: writes beyond the memory limit", _0)]
+    MemoryLimit(usize),
+}
+
+/// Runs a program of additions and multiplications once with the cells at
+/// `unknowns` holding `x0`, `x1`, ..., and returns the final memory as
+/// polynomials in them.
+///
+/// A cell read through an address that depends on an unknown could be any
+/// cell, so its value is `None`, as is everything computed from it.
+pub fn evaluate_symbolic(
+    program: &[i64],
+    unknowns: &[usize],
+) -> Result<Vec<Option<Polynomial>>, SymbolicError> {
+    let mut memory: Vec<_> = program
+        .iter()
+        .map(|&word| Some(Polynomial::constant(word)))
+        .collect();
+    for (n, &address) in unknowns.iter().enumerate() {
+        if address >= memory.len() {
+            memory.resize(address + 1, Some(Polynomial::default()));
+        }
+        memory[address] = Some(Polynomial::unknown(n));
+    }
+    let zero = Some(Polynomial::default());
+    let mut ip = 0;
+    loop {
+        let constant = |memory: &[Option<Polynomial>], address: usize| {
+            let cell = memory.get(address).unwrap_or(&zero);
+            cell.as_ref().and_then(Polynomial::as_constant)
+        };
+        let word = constant(&memory, ip).ok_or(SymbolicError::UnknownInstruction(ip))?;
+        let param = Parameter::try_from(word)
+            .ok()
+            .filter(|param| matches!(param.opcode, 1 | 2 | 99))
+            .ok_or(SymbolicError::Unsupported { address: ip, word })?;
+        if param.opcode == 99 {
+            return Ok(memory);
+        }
+        let modes = param.modes();
+        let mut operands = Vec::new();
+        for (at, mode) in (ip + 1..).zip(&modes[..2]) {
+            let value = match mode {
+                ParameterMode::ImmediateMode => memory.get(at).unwrap_or(&zero).clone(),
+                // Without opcode 9 the relative base stays 0.
+                ParameterMode::PositionMode | ParameterMode::RelativeMode => {
+                    match constant(&memory, at) {
+                        Some(address) => {
+                            let address = usize::try_from(address)
+                                .map_err(|_| SymbolicError::NegativeAddress(ip))?;
+                            memory.get(address).unwrap_or(&zero).clone()
+                        }
+                        None => None,
+                    }
+                }
+            };
+            operands.push(value);
+        }
+        let target = match modes[2] {
+            ParameterMode::ImmediateMode => {
+                return Err(SymbolicError::Unsupported { address: ip, word })
+            }
+            _ => constant(&memory, ip + 3).ok_or(SymbolicError::UnknownWrite(ip))?,
+        };
+        let target = usize::try_from(target).map_err(|_| SymbolicError::NegativeAddress(ip))?;
+        let result = match (&operands[0], &operands[1]) {
+            (Some(a), Some(b)) => {
+                let result = match param.opcode {
+                    1 => a.checked_add(b),
+                    _ => a.checked_mul(b),
+                };
+                Some(result.ok_or(SymbolicError::Overflow(ip))?)
+            }
+            _ => None,
+        };
+        if target >= DEFAULT_MEMORY_LIMIT {
+            return Err(SymbolicError::MemoryLimit(ip));
+        }
+        if target >= memory.len() {
+            memory.resize(target + 1, zero.clone());
+        }
+        memory[target] = result;
+        ip += 4;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{loader::load, machine::Machine};
+
+    use std::collections::VecDeque;
+
+    #[test]
+    fn test_polynomial() {
+        let (x, y) = (Polynomial::unknown(0), Polynomial::unknown(1));
+        let p = x
+            .checked_mul(&x)
+            .unwrap()
+            .checked_mul(&Polynomial::constant(3))
+            .unwrap()
+            .checked_add(&y.checked_mul(&Polynomial::constant(-2)).unwrap())
+            .unwrap()
+            .checked_add(&Polynomial::constant(7))
+            .unwrap();
+        assert_eq!(p.to_string(), "3*x0^2 - 2*x1 + 7");
+        assert_eq!(p.format(&["noun", "verb"]), "3*noun^2 - 2*verb + 7");
+        assert_eq!((p.degree(), p.evaluate(&[2, 5])), (2, Some(9)));
+        assert_eq!(p.linear(2), None);
+        assert_eq!(
+            p.checked_add(&p.checked_mul(&Polynomial::constant(-1)).unwrap())
+                .unwrap()
+                .as_constant(),
+            Some(0)
+        );
+
+        let p = x
+            .checked_mul(&Polynomial::constant(100))
+            .unwrap()
+            .checked_add(&y)
+            .unwrap();
+        let linear = p.linear(2).unwrap();
+        assert_eq!(linear.coefficients, vec![100, 1]);
+        assert_eq!(linear.solve(1234, &[0..=99, 0..=99]), Some(vec![12, 34]));
+        assert_eq!(linear.solve(12345, &[0..=99, 0..=99]), None);
+        assert_eq!(linear.solve(1234, &[0..=99]), None);
+        assert_eq!(p.linear(1), None);
+    }
+
+    #[test]
+    fn test_day_02() {
+        let program = load("../day_02/input.txt").unwrap();
+        let memory = evaluate_symbolic(&program, &[1, 2]).unwrap();
+        let result = memory[0].as_ref().unwrap();
+        let linear = result.linear(2).unwrap();
+        let solution = linear.solve(19690720, &[0..=99, 0..=99]).unwrap();
+
+        let mut machine = Machine::new(program);
+        machine.memory_mut().set(1, solution[0]).unwrap();
+        machine.memory_mut().set(2, solution[1]).unwrap();
+        machine
+            .run_to_halt(&mut VecDeque::new(), &mut Vec::new())
+            .unwrap();
+        assert_eq!(machine.memory()[0], 19690720);
+        assert_eq!(result.evaluate(&solution), Some(19690720));
+    }
+
+    #[test]
+    fn test_errors() {
+        let memory = evaluate_symbolic(&[1, 0, 0, 3, 2, 1, 2, 0, 99], &[1, 2]).unwrap();
+        assert_eq!(memory[3], None);
+        assert_eq!(memory[0].as_ref().unwrap().to_string(), "x0*x1");
+
+        let error = |program: &[i64]| evaluate_symbolic(program, &[1, 2]).unwrap_err();
+        assert_eq!(
+            error(&[1101, 0, 0, 4, 0, 0, 0, 0, 99]),
+            SymbolicError::UnknownInstruction(4)
+        );
+        assert_eq!(
+            error(&[1101, 0, 0, 7, 1101, 0, 0, 0, 99]),
+            SymbolicError::UnknownWrite(4)
+        );
+        assert_eq!(
+            error(&[1, 0, 0, 1 << 40, 99]),
+            SymbolicError::MemoryLimit(0)
+        );
+        assert_eq!(
+            error(&[3, 0, 0, 0]),
+            SymbolicError::Unsupported {
+                address: 0,
+                word: 3
+            }
+        );
+    }
+}
@@ -0,0 +1,316 @@
+use crate::{
+    disasm::{format_instruction, label_name},
+    error::Error,
+    io::{Input, Output},
+    machine::{Machine, Status},
+    parameter::{Parameter, ParameterMode},
+};
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    fmt::{self, Write},
+};
+
+/// What a machine did while run by a [`Profiler`].
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct Profile {
+    /// How often the instruction at each address was executed.
+    pub executions: BTreeMap<usize, u64>,
+    /// How often each opcode was executed.
+    pub opcodes: BTreeMap<usize, u64>,
+    /// How often each address was read as an argument.
+    pub reads: BTreeMap<usize, u64>,
+    /// How often each address was written to.
+    pub writes: BTreeMap<usize, u64>,
+    /// The words of the instruction last executed at each address.
+    pub code: BTreeMap<usize, Vec<i64>>,
+    /// Addresses executed first, or right after a jump.
+    pub leaders: BTreeSet<usize>,
+}
+
+impl Profile {
+    /// How many instructions were executed in total.
+    pub fn total(&self) -> u64 {
+        self.executions.values().sum()
+    }
+
+    /// The `n` most executed addresses with their counts, hottest first.
+    pub fn hottest(&self, n: usize) -> Vec<(usize, u64)> {
+        let mut hottest: Vec<_> = self.executions.iter().map(|(&a, &c)| (a, c)).collect();
+        hottest.sort_by_key(|&(address, count)| (std::cmp::Reverse(count), address));
+        hottest.truncate(n);
+        hottest
+    }
+
+    /// The executed instructions grouped into basic blocks, as the start of
+    /// every block with how many instructions were executed in it.
+    ///
+    /// A block ends at a jump or a halt, and before every address that was
+    /// entered by a jump, so it only ever runs as a whole.
+    pub fn blocks(&self) -> BTreeMap<usize, u64> {
+        let mut blocks = BTreeMap::new();
+        let mut current: Option<(usize, usize)> = None;
+        for (&address, &count) in &self.executions {
+            let words = &self.code[&address];
+            let continues = match current {
+                Some((_, next)) => next == address && !self.leaders.contains(&address),
+                None => false,
+            };
+            let start = match (continues, current) {
+                (true, Some((start, _))) => start,
+                _ => address,
+            };
+            *blocks.entry(start).or_insert(0) += count;
+            let ends = match Parameter::try_from(words[0]) {
+                Ok(param) => matches!(param.opcode, 5 | 6 | 99),
+                Err(_) => true,
+            };
+            current = match ends {
+                true => None,
+                false => Some((start, address + words.len())),
+            };
+        }
+        blocks
+    }
+
+    /// The block profile in the folded stack format read by flamegraph
+    /// tools, one `label count` line per block.
+    pub fn folded(&self) -> String {
+        let mut folded = String::new();
+        for (start, count) in self.blocks() {
+            writeln!(folded, "{} {}", label_name(start), count).unwrap();
+        }
+        folded
+    }
+
+    /// A readable summary: the `top` hottest addresses with their
+    /// disassembly, executions per opcode, and the `top` most accessed
+    /// memory cells.
+    pub fn report(&self, top: usize) -> String {
+        let mut report = String::new();
+        self.write_report(top, &mut report).unwrap();
+        report
+    }
+
+    fn write_report(&self, top: usize, f: &mut dyn Write) -> fmt::Result {
+        let total = self.total();
+        let percent = |count: u64| 100.0 * count as f64 / total.max(1) as f64;
+        writeln!(f, "{} instructions executed", total)?;
+
+        writeln!(f, "\nhottest addresses:")?;
+        for (address, count) in self.hottest(top) {
+            let words = &self.code[&address];
+            let text = format_instruction(words).unwrap_or_else(|| format!("{:?}", words));
+            writeln!(
+                f,
+                "{:>8} {:>10} {:>6.2}%  {}",
+                address,
+                count,
+                percent(count),
+                text
+            )?;
+        }
+
+        writeln!(f, "\nopcodes:")?;
+        for (&opcode, &count) in &self.opcodes {
+            let mnemonic = Parameter::try_from(opcode as i64)
+                .ok()
+                .and_then(Parameter::mnemonic)
+                .unwrap_or("?");
+            writeln!(f, "{:>8} {:>10} {:>6.2}%", mnemonic, count, percent(count))?;
+        }
+
+        writeln!(f, "\nmost accessed memory:")?;
+        writeln!(f, "{:>8} {:>10} {:>10}", "address", "reads", "writes")?;
+        let accessed: BTreeSet<_> = self.reads.keys().chain(self.writes.keys()).collect();
+        let count = |map: &BTreeMap<usize, u64>, address| map.get(address).copied().unwrap_or(0);
+        let mut accessed: Vec<_> = accessed
+            .into_iter()
+            .map(|address| {
+                (
+                    address,
+                    count(&self.reads, address),
+                    count(&self.writes, address),
+                )
+            })
+            .collect();
+        accessed
+            .sort_by_key(|&(address, reads, writes)| (std::cmp::Reverse(reads + writes), *address));
+        for (address, reads, writes) in accessed.into_iter().take(top) {
+            writeln!(f, "{:>8} {:>10} {:>10}", address, reads, writes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs a machine while profiling every instruction it executes.
+#[derive(Debug, Default)]
+pub struct Profiler {
+    profile: Profile,
+    /// Whether the last instruction was a jump.
+    jumped: bool,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+
+    pub fn into_profile(self) -> Profile {
+        self.profile
+    }
+
+    /// Executes one instruction, counting what it does.
+    pub fn step<I: Input + ?Sized, O: Output + ?Sized>(
+        &mut self,
+        machine: &mut Machine,
+        inn: &mut I,
+        out: &mut O,
+    ) -> Result<Status, Error> {
+        if machine.status() == Status::Halted {
+            return Ok(Status::Halted);
+        }
+        let ip = machine.ip();
+        let relative_base = machine.relative_base();
+        let memory = machine.memory();
+        // Decoded before running the instruction, which may overwrite
+        // itself. If it does not decode, `step` fails below.
+        let decoded = Parameter::try_from(memory[ip])
+            .ok()
+            .and_then(|param| param.arg_count().map(|count| (param, count)));
+        let (words, reads, write) = match decoded {
+            Some((param, count)) => {
+                let words = (ip..=ip + count).map(|address| memory[address]).collect();
+                let sources = match param.writes() {
+                    true => count - 1,
+                    false => count,
+                };
+                let reads: Vec<_> = param.modes()[..sources]
+                    .iter()
+                    .zip(ip + 1..)
+                    .filter_map(|(&mode, at)| {
+                        let offset = memory[at];
+                        let address = match mode {
+                            ParameterMode::ImmediateMode => return None,
+                            ParameterMode::PositionMode => offset,
+                            ParameterMode::RelativeMode => relative_base.checked_add(offset)?,
+                        };
+                        usize::try_from(address).ok()
+                    })
+                    .collect();
+                (words, reads, param.write_address(ip, memory, relative_base))
+            }
+            None => (Vec::new(), Vec::new(), None),
+        };
+        let status = machine.step(inn, out)?;
+        if status == Status::Blocking {
+            return Ok(status);
+        }
+
+        let profile = &mut self.profile;
+        let param = Parameter::try_from(words[0]).unwrap();
+        *profile.executions.entry(ip).or_insert(0) += 1;
+        *profile.opcodes.entry(param.opcode).or_insert(0) += 1;
+        for address in reads {
+            *profile.reads.entry(address).or_insert(0) += 1;
+        }
+        if let Some(address) = write {
+            *profile.writes.entry(address).or_insert(0) += 1;
+        }
+        if self.jumped || profile.leaders.is_empty() {
+            profile.leaders.insert(ip);
+        }
+        self.jumped = matches!(param.opcode, 5 | 6);
+        profile.code.insert(ip, words);
+        Ok(status)
+    }
+
+    /// Runs until the machine halts or blocks, like [`Machine::run`].
+    pub fn run<I: Input + ?Sized, O: Output + ?Sized>(
+        &mut self,
+        machine: &mut Machine,
+        inn: &mut I,
+        out: &mut O,
+    ) -> Result<Status, Error> {
+        loop {
+            match self.step(machine, inn, out)? {
+                Status::Running => (),
+                status => return Ok(status),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm::assemble, loader::parse_program};
+
+    use std::{collections::VecDeque, fs::read_to_string};
+
+    #[test]
+    fn test_counts() {
+        let program = assemble(
+            "
+                in -> [n]
+            loop:
+                add [sum], [n] -> [sum]
+                add [n], #-1 -> [n]
+                jnz [n], loop
+                out [sum]
+                hlt
+            n:   .data 0
+            sum: .data 0
+            ",
+        )
+        .unwrap();
+        let mut profiler = Profiler::new();
+        let mut out = Vec::new();
+        let status = profiler
+            .run(
+                &mut Machine::new(program),
+                &mut VecDeque::from(vec![10]),
+                &mut out,
+            )
+            .unwrap();
+        assert_eq!((status, out), (Status::Halted, vec![55]));
+
+        let profile = profiler.into_profile();
+        assert_eq!(profile.total(), 1 + 3 * 10 + 2);
+        assert_eq!(profile.hottest(2), vec![(2, 10), (6, 10)]);
+        assert_eq!(profile.opcodes[&1], 20);
+        let (n, sum) = (16, 17);
+        assert_eq!((profile.reads[&n], profile.writes[&n]), (30, 11));
+        assert_eq!((profile.reads[&sum], profile.writes[&sum]), (11, 10));
+        assert_eq!(profile.folded(), "L0000 1\nL0002 30\nL0013 2\n");
+
+        let report = profile.report(2);
+        assert!(report.starts_with("33 instructions executed\n"));
+        assert!(report.contains("       2         10  30.30%  ADD [17], [16] -> [17]\n"));
+        assert!(report.contains("     ADD         20  60.61%\n"));
+        assert!(report.contains("      16         30         11\n"));
+    }
+
+    #[test]
+    fn test_amplifier() {
+        let program = parse_program(&read_to_string("../day_07/input.txt").unwrap()).unwrap();
+        let mut machine = Machine::new(program);
+        let mut profiler = Profiler::new();
+        let mut out = Vec::new();
+        profiler
+            .run(&mut machine, &mut VecDeque::from(vec![3, 7]), &mut out)
+            .unwrap();
+        let profile = profiler.into_profile();
+        assert_eq!(out.len(), 1);
+        // The jump table splits the program into the dispatch and the code
+        // for one phase setting.
+        let blocks = profile.blocks();
+        assert_eq!(blocks.len(), 2);
+        assert_eq!(blocks.values().sum::<u64>(), profile.total());
+    }
+}
@@ -0,0 +1,154 @@
+// The impls generated by `err-derive` live inside an anonymous const.
+#![allow(non_local_definitions)]
+
+use crate::machine::{Machine, Status};
+
+use bincode::Options;
+use err_derive::Error;
+use serde::{Deserialize, Serialize};
+
+/// Leads every snapshot in the binary format, followed by its version.
+const MAGIC: &[u8; 4] = b"ICS\x01";
+
+/// The complete state of a [`Machine`], see [`Machine::snapshot`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The loaded program, with everything written to it since.
+    pub memory: Vec<i64>,
+    /// Non-zero cells written beyond `memory`, in address order.
+    pub sparse_memory: Vec<(usize, i64)>,
+    pub memory_limit: usize,
+    pub ip: usize,
+    pub relative_base: i64,
+    pub status: Status,
+    /// Input queued with [`Machine::push_input`] but not yet read.
+    pub input: Vec<i64>,
+}
+
+/// A snapshot could not be decoded.
+#[derive(Debug, Error, PartialEq, Eq, Clone)]
+pub enum SnapshotError {
+    #[error(display = "not an Intcode snapshot")]
+    NotASnapshot,
+    #[error(display = "malformed snapshot: {}", _0)]
+    Malformed(String),
+}
+
+/// Integers are variable length encoded, so small cells take a single byte.
+pub(crate) fn binary() -> impl Options {
+    bincode::DefaultOptions::new().reject_trailing_bytes()
+}
+
+impl Snapshot {
+    /// Encodes the snapshot in the compact binary format.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend(binary().serialize(self).unwrap());
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, SnapshotError> {
+        match bytes.strip_prefix(&MAGIC[..]) {
+            Some(bytes) => binary()
+                .deserialize(bytes)
+                .map_err(|err| SnapshotError::Malformed(err.to_string())),
+            None => Err(SnapshotError::NotASnapshot),
+        }
+    }
+
+    /// Encodes the snapshot as pretty printed JSON.
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).unwrap()
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, SnapshotError> {
+        serde_json::from_str(text).map_err(|err| SnapshotError::Malformed(err.to_string()))
+    }
+}
+
+impl From<&Snapshot> for Machine {
+    fn from(snapshot: &Snapshot) -> Self {
+        let mut machine = Machine::new(Vec::new());
+        machine.restore(snapshot);
+        machine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm::assemble, machine::Event};
+
+    /// Outputs `2 * input + 1` for every input until it reads zero.
+    fn machine() -> Machine {
+        let program = assemble(
+            "
+            loop:
+                in -> [x]
+                jz [x], end
+                mul [x], #2 -> [x]
+                add [x], #1 -> [1000000]
+                out [1000000]
+                jnz #1, loop
+            end:
+                hlt
+            x:  .data 0
+            ",
+        )
+        .unwrap();
+        Machine::new(program).with_memory_limit(1 << 21)
+    }
+
+    #[test]
+    fn test_fork() {
+        let mut machine = machine();
+        machine.push_input(3);
+        assert_eq!(machine.run_until_event(), Ok(Event::Output(7)));
+        machine.push_input(5);
+        let snapshot = machine.snapshot();
+        assert_eq!(snapshot.input, vec![5]);
+        assert_eq!(snapshot.sparse_memory, vec![(1000000, 7)]);
+
+        let mut fork = machine.clone();
+        assert_eq!(fork.run_until_event(), Ok(Event::Output(11)));
+        fork.push_input(0);
+        assert_eq!(fork.run_until_event(), Ok(Event::Halted));
+
+        fork.restore(&snapshot);
+        assert_eq!(fork.snapshot(), snapshot);
+        assert_eq!(fork.memory(), machine.memory());
+        assert_eq!(fork.run_until_event(), Ok(Event::Output(11)));
+        assert_eq!(
+            Machine::from(&snapshot).run_until_event(),
+            Ok(Event::Output(11))
+        );
+    }
+
+    #[test]
+    fn test_formats() {
+        let mut machine = machine();
+        machine.push_input(3);
+        machine.run_until_event().unwrap();
+        let snapshot = machine.snapshot();
+
+        let bytes = snapshot.to_bytes();
+        assert!(bytes.len() < 64, "{} bytes", bytes.len());
+        assert_eq!(Snapshot::from_bytes(&bytes), Ok(snapshot.clone()));
+        assert_eq!(
+            Snapshot::from_bytes(b"ICS"),
+            Err(SnapshotError::NotASnapshot)
+        );
+        match Snapshot::from_bytes(&bytes[..bytes.len() - 1]) {
+            Err(SnapshotError::Malformed(_)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let json = snapshot.to_json();
+        assert!(json.contains("\"status\": \"Running\""));
+        assert_eq!(Snapshot::from_json(&json), Ok(snapshot));
+        match Snapshot::from_json("{}") {
+            Err(SnapshotError::Malformed(_)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,859 @@
+use crate::{
+    error::{Error, ErrorKind},
+    memory::DEFAULT_MEMORY_LIMIT,
+    parameter::{Parameter, ParameterMode},
+};
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    convert::TryFrom,
+    fmt,
+    ops::RangeInclusive,
+    rc::Rc,
+};
+
+/// A value computed from the inputs of a program.
+///
+/// The constructors fold constants, so an expression that does not depend
+/// on any input is always a [`Expr::Const`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Expr {
+    Const(i64),
+    /// The input read by the `n`th executed opcode 3, counted from 0.
+    Input(usize),
+    Add(Rc<Expr>, Rc<Expr>),
+    Mul(Rc<Expr>, Rc<Expr>),
+    /// 1 if the first is less than the second, otherwise 0.
+    LessThan(Rc<Expr>, Rc<Expr>),
+    /// 1 if both are equal, otherwise 0.
+    Equals(Rc<Expr>, Rc<Expr>),
+}
+
+impl Expr {
+    pub fn as_const(&self) -> Option<i64> {
+        match *self {
+            Expr::Const(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    /// `None` if adding two constants overflows. Every input is assumed to
+    /// lie in `inputs`, which decides whether `(x + c) + d` can fold into
+    /// `x + (c + d)`: only where `x + c` cannot overflow do both overflow
+    /// for the same inputs.
+    pub fn checked_add(self, other: Expr, inputs: &RangeInclusive<i64>) -> Option<Expr> {
+        Some(match (self, other) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const(a.checked_add(b)?),
+            (Expr::Const(0), x) | (x, Expr::Const(0)) => x,
+            (Expr::Const(c), x) => x.checked_add(Expr::Const(c), inputs)?,
+            (Expr::Add(x, c), Expr::Const(d)) => {
+                let sum = c
+                    .as_const()
+                    .filter(|&c| {
+                        x.bounds(inputs)
+                            .and_then(|x| add_bounds(x, (c, c)))
+                            .is_some()
+                    })
+                    .and_then(|c| c.checked_add(d));
+                match sum {
+                    Some(sum) => Expr::Add(x, Rc::new(Expr::Const(sum))),
+                    None => Expr::Add(Rc::new(Expr::Add(x, c)), Rc::new(Expr::Const(d))),
+                }
+            }
+            (a, b) => Expr::Add(Rc::new(a), Rc::new(b)),
+        })
+    }
+
+    /// `None` if multiplying two constants overflows. Like
+    /// [`Expr::checked_add`], `(x * c) * d` only folds into `x * (c * d)`
+    /// where `x * c` cannot overflow for inputs in `inputs`.
+    pub fn checked_mul(self, other: Expr, inputs: &RangeInclusive<i64>) -> Option<Expr> {
+        Some(match (self, other) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const(a.checked_mul(b)?),
+            (Expr::Const(0), _) | (_, Expr::Const(0)) => Expr::Const(0),
+            (Expr::Const(1), x) | (x, Expr::Const(1)) => x,
+            (Expr::Const(c), x) => x.checked_mul(Expr::Const(c), inputs)?,
+            (Expr::Mul(x, c), Expr::Const(d)) => {
+                let product = c
+                    .as_const()
+                    .filter(|&c| {
+                        x.bounds(inputs)
+                            .and_then(|x| mul_bounds(x, (c, c)))
+                            .is_some()
+                    })
+                    .and_then(|c| c.checked_mul(d));
+                match product {
+                    Some(product) => Expr::Mul(x, Rc::new(Expr::Const(product))),
+                    None => Expr::Mul(Rc::new(Expr::Mul(x, c)), Rc::new(Expr::Const(d))),
+                }
+            }
+            (a, b) => Expr::Mul(Rc::new(a), Rc::new(b)),
+        })
+    }
+
+    pub fn less_than(self, other: Expr) -> Expr {
+        match (self, other) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const((a < b) as i64),
+            (a, b) if a == b => Expr::Const(0),
+            (a, b) => Expr::LessThan(Rc::new(a), Rc::new(b)),
+        }
+    }
+
+    /// Like [`Expr::checked_add`], folds only where no part of the
+    /// comparison that the fold drops can overflow for inputs in `inputs`.
+    pub fn equals(self, other: Expr, inputs: &RangeInclusive<i64>) -> Expr {
+        match (self, other) {
+            (Expr::Const(a), Expr::Const(b)) => Expr::Const((a == b) as i64),
+            (a, b) if a == b && a.bounds(inputs).is_some() => Expr::Const(1),
+            // x + c == d is x == d - c, as long as x + c does not overflow.
+            (Expr::Add(x, c), Expr::Const(d)) => {
+                let rest = c
+                    .as_const()
+                    .filter(|&c| {
+                        x.bounds(inputs)
+                            .and_then(|x| add_bounds(x, (c, c)))
+                            .is_some()
+                    })
+                    .and_then(|c| d.checked_sub(c));
+                match rest {
+                    Some(rest) => (*x).clone().equals(Expr::Const(rest), inputs),
+                    None => Expr::Equals(Rc::new(Expr::Add(x, c)), Rc::new(Expr::Const(d))),
+                }
+            }
+            // Comparisons are only ever 0 or 1.
+            (Expr::LessThan(..), Expr::Const(c)) | (Expr::Equals(..), Expr::Const(c))
+                if c != 0 && c != 1 =>
+            {
+                Expr::Const(0)
+            }
+            (a, b) => Expr::Equals(Rc::new(a), Rc::new(b)),
+        }
+    }
+
+    /// The smallest and largest value for inputs in `inputs`, `None` if it
+    /// can overflow for some of them.
+    pub fn bounds(&self, inputs: &RangeInclusive<i64>) -> Option<(i64, i64)> {
+        match self {
+            Expr::Const(value) => Some((*value, *value)),
+            Expr::Input(_) => Some((*inputs.start(), *inputs.end())),
+            Expr::Add(a, b) => add_bounds(a.bounds(inputs)?, b.bounds(inputs)?),
+            Expr::Mul(a, b) => mul_bounds(a.bounds(inputs)?, b.bounds(inputs)?),
+            Expr::LessThan(..) | Expr::Equals(..) => Some((0, 1)),
+        }
+    }
+
+    /// The value for the given inputs, `None` if it overflows or needs an
+    /// input past the end of them.
+    pub fn evaluate(&self, inputs: &[i64]) -> Option<i64> {
+        match self {
+            Expr::Const(value) => Some(*value),
+            Expr::Input(n) => inputs.get(*n).copied(),
+            Expr::Add(a, b) => a.evaluate(inputs)?.checked_add(b.evaluate(inputs)?),
+            Expr::Mul(a, b) => a.evaluate(inputs)?.checked_mul(b.evaluate(inputs)?),
+            Expr::LessThan(a, b) => Some((a.evaluate(inputs)? < b.evaluate(inputs)?) as i64),
+            Expr::Equals(a, b) => Some((a.evaluate(inputs)? == b.evaluate(inputs)?) as i64),
+        }
+    }
+}
+
+fn add_bounds((a, b): (i64, i64), (c, d): (i64, i64)) -> Option<(i64, i64)> {
+    Some((a.checked_add(c)?, b.checked_add(d)?))
+}
+
+fn mul_bounds((a, b): (i64, i64), (c, d): (i64, i64)) -> Option<(i64, i64)> {
+    let corners = [
+        a.checked_mul(c)?,
+        a.checked_mul(d)?,
+        b.checked_mul(c)?,
+        b.checked_mul(d)?,
+    ];
+    Some((*corners.iter().min()?, *corners.iter().max()?))
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Expr::Const(value) => write!(f, "{}", value),
+            Expr::Input(n) => write!(f, "in{}", n),
+            Expr::Add(a, b) => write!(f, "({} + {})", a, b),
+            Expr::Mul(a, b) => write!(f, "({} * {})", a, b),
+            Expr::LessThan(a, b) => write!(f, "({} < {})", a, b),
+            Expr::Equals(a, b) => write!(f, "({} == {})", a, b),
+        }
+    }
+}
+
+/// A condition a path depends on: whether `condition` is non-zero.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Constraint {
+    pub condition: Expr,
+    pub holds: bool,
+}
+
+impl Constraint {
+    /// The constraint that `expr` equals `value`, for inputs in `inputs`.
+    pub fn equals(expr: Expr, value: i64, inputs: &RangeInclusive<i64>) -> Self {
+        Self {
+            condition: expr.equals(Expr::Const(value), inputs),
+            holds: true,
+        }
+    }
+
+    /// Whether the constraint is met by the given inputs.
+    pub fn check(&self, inputs: &[i64]) -> bool {
+        self.condition
+            .evaluate(inputs)
+            .is_some_and(|value| (value != 0) == self.holds)
+    }
+}
+
+impl fmt::Display for Constraint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.condition, self.holds) {
+            (Expr::LessThan(a, b), true) => write!(f, "{} < {}", a, b),
+            (Expr::LessThan(a, b), false) => write!(f, "{} >= {}", a, b),
+            (Expr::Equals(a, b), true) => write!(f, "{} == {}", a, b),
+            (Expr::Equals(a, b), false) => write!(f, "{} != {}", a, b),
+            (condition, true) => write!(f, "{} != 0", condition),
+            (condition, false) => write!(f, "{} == 0", condition),
+        }
+    }
+}
+
+/// A value output on a path, with the constraints the path had met by then.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SymbolicOutput {
+    pub value: Expr,
+    pub constraints: Vec<Constraint>,
+}
+
+/// Why a path stopped.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum End {
+    Halted,
+    /// An instruction failed for every input on the path.
+    Error(Error),
+    /// The program read more inputs than the executor provides.
+    InputLimit,
+    StepLimit,
+    /// Forking the path would exceed the path limit.
+    PathLimit,
+    /// The instruction at the address needs a value that depends on the
+    /// inputs, and it has too many possible values to fork on each, or none
+    /// that can be used.
+    Unresolved(usize),
+}
+
+/// One way through a program, see [`SymbolicExecutor::explore`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Path {
+    /// What the inputs must satisfy to take this path.
+    pub constraints: Vec<Constraint>,
+    pub outputs: Vec<SymbolicOutput>,
+    /// How many inputs the path read.
+    pub inputs: usize,
+    pub steps: usize,
+    pub end: End,
+}
+
+/// A machine state while exploring, with memory written since the start
+/// kept on top of the shared program.
+#[derive(Debug, Clone)]
+struct State {
+    ip: usize,
+    relative_base: i64,
+    writes: BTreeMap<usize, Expr>,
+    constraints: Vec<Constraint>,
+    outputs: Vec<SymbolicOutput>,
+    inputs: usize,
+    steps: usize,
+}
+
+/// What a single step did.
+enum Step {
+    Continue,
+    End(End),
+    /// The step could not run as a whole, but can in each of these states.
+    Fork(Vec<State>),
+}
+
+/// Executes a program on symbolic inputs, following every path the inputs
+/// can take it down.
+///
+/// Values read by opcode 3 are the unknowns `in0`, `in1`, ..., and every
+/// write and output is an [`Expr`] of them. A conditional jump on an
+/// expression forks the path, with the condition added to the constraints
+/// of each side. Where an instruction needs a concrete value, such as an
+/// address or an instruction word that depends on the inputs, the path
+/// forks on every value it can take.
+///
+/// Which branches are possible is decided by trying every combination of
+/// inputs in the search range, so inputs are assumed to lie in it. Inputs
+/// that turn an instruction word into an invalid one are not followed.
+#[derive(Debug, Clone)]
+pub struct SymbolicExecutor {
+    program: Rc<[i64]>,
+    max_inputs: usize,
+    max_steps: usize,
+    max_paths: usize,
+    max_forks: usize,
+    search: RangeInclusive<i64>,
+}
+
+impl SymbolicExecutor {
+    pub fn new(program: Vec<i64>) -> Self {
+        Self {
+            program: program.into(),
+            max_inputs: 2,
+            max_steps: 100_000,
+            max_paths: 1000,
+            max_forks: 64,
+            search: -100..=100,
+        }
+    }
+
+    /// How many inputs a path may read before it ends.
+    pub fn max_inputs(&mut self, max_inputs: usize) -> &mut Self {
+        self.max_inputs = max_inputs;
+        self
+    }
+
+    /// How many instructions a path may execute before it ends.
+    pub fn max_steps(&mut self, max_steps: usize) -> &mut Self {
+        self.max_steps = max_steps;
+        self
+    }
+
+    /// How many paths are explored at most.
+    pub fn max_paths(&mut self, max_paths: usize) -> &mut Self {
+        self.max_paths = max_paths;
+        self
+    }
+
+    /// How many values a path may fork on where an instruction needs a
+    /// concrete value that depends on the inputs.
+    pub fn max_forks(&mut self, max_forks: usize) -> &mut Self {
+        self.max_forks = max_forks;
+        self
+    }
+
+    /// The values every input is assumed to lie within.
+    pub fn search(&mut self, search: RangeInclusive<i64>) -> &mut Self {
+        self.search = search;
+        self
+    }
+
+    /// Follows every path from the start of the program, depth first.
+    pub fn explore(&self) -> Vec<Path> {
+        let mut paths = Vec::new();
+        let mut work = vec![State {
+            ip: 0,
+            relative_base: 0,
+            writes: BTreeMap::new(),
+            constraints: Vec::new(),
+            outputs: Vec::new(),
+            inputs: 0,
+            steps: 0,
+        }];
+        while let Some(mut state) = work.pop() {
+            let end = loop {
+                if state.steps >= self.max_steps {
+                    break Some(End::StepLimit);
+                }
+                match self.step(&mut state) {
+                    Step::Continue => state.steps += 1,
+                    Step::End(end) => break Some(end),
+                    Step::Fork(_) if paths.len() + work.len() + 2 > self.max_paths => {
+                        break Some(End::PathLimit)
+                    }
+                    Step::Fork(forks) => {
+                        // Pushed in reverse, so the first fork runs first.
+                        work.extend(forks.into_iter().rev());
+                        break None;
+                    }
+                }
+            };
+            // A forked state lives on in its forks.
+            if let Some(end) = end {
+                paths.push(Path {
+                    constraints: state.constraints,
+                    outputs: state.outputs,
+                    inputs: state.inputs,
+                    steps: state.steps,
+                    end,
+                });
+            }
+        }
+        paths
+    }
+
+    /// Calls `found` with every combination of the first `inputs` inputs in
+    /// the search range that meets all `constraints`, until it returns false.
+    fn search_inputs(
+        &self,
+        constraints: &[Constraint],
+        inputs: usize,
+        mut found: impl FnMut(&[i64]) -> bool,
+    ) {
+        if self.search.is_empty() {
+            return;
+        }
+        let mut values = vec![*self.search.start(); inputs];
+        loop {
+            if constraints.iter().all(|c| c.check(&values)) && !found(&values) {
+                return;
+            }
+            let mut n = values.len();
+            loop {
+                match n.checked_sub(1) {
+                    None => return,
+                    Some(m) => n = m,
+                }
+                match values[n] < *self.search.end() {
+                    true => {
+                        values[n] += 1;
+                        break;
+                    }
+                    false => values[n] = *self.search.start(),
+                }
+            }
+        }
+    }
+
+    /// The first inputs in the search range that meet all `constraints`.
+    pub fn solve(&self, constraints: &[Constraint], inputs: usize) -> Option<Vec<i64>> {
+        let mut solution = None;
+        self.search_inputs(constraints, inputs, |values| {
+            solution = Some(values.to_vec());
+            false
+        });
+        solution
+    }
+
+    fn cell(&self, state: &State, address: usize) -> Expr {
+        match state.writes.get(&address) {
+            Some(expr) => expr.clone(),
+            None => Expr::Const(self.program.get(address).copied().unwrap_or(0)),
+        }
+    }
+
+    /// The value of the cell at `address` if it is constant. Otherwise the
+    /// step forks into a state for every value the cell can take, with the
+    /// cell set to it, keeping only the values `keep` accepts.
+    fn concrete(
+        &self,
+        state: &State,
+        ip: usize,
+        address: usize,
+        keep: impl Fn(i64) -> bool,
+    ) -> Result<i64, Step> {
+        let expr = self.cell(state, address);
+        if let Some(value) = expr.as_const() {
+            return Ok(value);
+        }
+        let mut values = BTreeSet::new();
+        self.search_inputs(&state.constraints, state.inputs, |inputs| {
+            if let Some(value) = expr.evaluate(inputs).filter(|&value| keep(value)) {
+                values.insert(value);
+            }
+            values.len() <= self.max_forks
+        });
+        if values.is_empty() || values.len() > self.max_forks {
+            return Err(Step::End(End::Unresolved(ip)));
+        }
+        let forks = values
+            .into_iter()
+            .map(|value| {
+                let mut fork = state.clone();
+                fork.constraints
+                    .push(Constraint::equals(expr.clone(), value, &self.search));
+                fork.writes.insert(address, Expr::Const(value));
+                fork
+            })
+            .collect();
+        Err(Step::Fork(forks))
+    }
+
+    /// The address argument `n` of the instruction at `ip` refers to.
+    fn address(
+        &self,
+        state: &State,
+        ip: usize,
+        mode: ParameterMode,
+        n: usize,
+    ) -> Result<Result<usize, ErrorKind>, Step> {
+        let offset = self.concrete(state, ip, ip + n, |_| true)?;
+        let address = match mode {
+            ParameterMode::RelativeMode => match state.relative_base.checked_add(offset) {
+                Some(address) => address,
+                None => return Ok(Err(ErrorKind::ArithmeticOverflow)),
+            },
+            _ => offset,
+        };
+        Ok(match usize::try_from(address) {
+            Ok(address) if address < DEFAULT_MEMORY_LIMIT => Ok(address),
+            Ok(address) => Err(ErrorKind::MemoryLimit {
+                address,
+                limit: DEFAULT_MEMORY_LIMIT,
+            }),
+            Err(_) => Err(ErrorKind::NegativeAddress(address.into())),
+        })
+    }
+
+    fn step(&self, state: &mut State) -> Step {
+        match self.try_step(state) {
+            Ok(step) => step,
+            Err(step) => step,
+        }
+    }
+
+    fn try_step(&self, state: &mut State) -> Result<Step, Step> {
+        let ip = state.ip;
+        let decodes =
+            |word: i64| Parameter::try_from(word).is_ok_and(|param| param.arg_count().is_some());
+        let word = self.concrete(state, ip, ip, decodes)?;
+        let fail = |kind| {
+            Step::End(End::Error(Error {
+                ip,
+                instruction: word.into(),
+                kind,
+            }))
+        };
+        let param = Parameter::try_from(word).map_err(fail)?;
+        let count = param
+            .arg_count()
+            .ok_or_else(|| fail(ErrorKind::UnknownOpcode))?;
+        let modes = param.modes();
+        let read = |state: &State, n: usize| -> Result<Expr, Step> {
+            match modes[n - 1] {
+                ParameterMode::ImmediateMode => Ok(self.cell(state, ip + n)),
+                mode => {
+                    let address = self.address(state, ip, mode, n)?.map_err(fail)?;
+                    Ok(self.cell(state, address))
+                }
+            }
+        };
+        // Like `read`, for arguments that need a concrete value.
+        let read_concrete = |state: &State, n: usize| -> Result<i64, Step> {
+            let address = match modes[n - 1] {
+                ParameterMode::ImmediateMode => ip + n,
+                mode => self.address(state, ip, mode, n)?.map_err(fail)?,
+            };
+            self.concrete(state, ip, address, |_| true)
+        };
+        let write = |state: &mut State, n: usize, value: Expr| -> Result<(), Step> {
+            let address = match modes[n - 1] {
+                ParameterMode::ImmediateMode => Err(ErrorKind::WriteInImmediateMode),
+                mode => self.address(state, ip, mode, n)?,
+            };
+            state.writes.insert(address.map_err(fail)?, value);
+            Ok(())
+        };
+        let overflow = || fail(ErrorKind::ArithmeticOverflow);
+        match param.opcode {
+            1 | 2 | 7 | 8 => {
+                let (a, b) = (read(state, 1)?, read(state, 2)?);
+                let value = match param.opcode {
+                    1 => a.checked_add(b, &self.search).ok_or_else(overflow)?,
+                    2 => a.checked_mul(b, &self.search).ok_or_else(overflow)?,
+                    7 => a.less_than(b),
+                    _ => a.equals(b, &self.search),
+                };
+                write(state, 3, value)?;
+            }
+            3 => {
+                if state.inputs == self.max_inputs {
+                    return Ok(Step::End(End::InputLimit));
+                }
+                write(state, 1, Expr::Input(state.inputs))?;
+                state.inputs += 1;
+            }
+            4 => {
+                let value = read(state, 1)?;
+                let constraints = state.constraints.clone();
+                state.outputs.push(SymbolicOutput { value, constraints });
+            }
+            5 | 6 => {
+                let condition = read(state, 1)?;
+                let jumps = |holds: bool| holds == (param.opcode == 5);
+                let holds = match condition.as_const() {
+                    Some(value) => value != 0,
+                    None => {
+                        let known = state.constraints.iter().find(|c| c.condition == condition);
+                        match known {
+                            Some(constraint) => constraint.holds,
+                            None => return Ok(self.branch(state, condition)),
+                        }
+                    }
+                };
+                if jumps(holds) {
+                    let target = read_concrete(state, 2)?;
+                    state.ip = usize::try_from(target)
+                        .map_err(|_| fail(ErrorKind::NegativeAddress(target.into())))?;
+                    return Ok(Step::Continue);
+                }
+            }
+            9 => {
+                let adjust = read_concrete(state, 1)?;
+                state.relative_base = state
+                    .relative_base
+                    .checked_add(adjust)
+                    .ok_or_else(overflow)?;
+            }
+            _ => return Ok(Step::End(End::Halted)),
+        }
+        state.ip += count + 1;
+        Ok(Step::Continue)
+    }
+
+    /// Forks on whether `condition` is non-zero, for each side that some
+    /// inputs can take.
+    fn branch(&self, state: &State, condition: Expr) -> Step {
+        let forks = [true, false]
+            .iter()
+            .filter_map(|&holds| {
+                let mut fork = state.clone();
+                fork.constraints.push(Constraint {
+                    condition: condition.clone(),
+                    holds,
+                });
+                self.solve(&fork.constraints, fork.inputs)?;
+                Some(fork)
+            })
+            .collect();
+        Step::Fork(forks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm::assemble, loader::load, machine::Machine};
+
+    use std::collections::VecDeque;
+
+    #[test]
+    fn test_folding() {
+        let search = -100..=100;
+        let x = Expr::Input(0);
+        let sum = x.clone().checked_add(Expr::Const(3), &search).unwrap();
+        let sum = sum.checked_add(Expr::Const(4), &search).unwrap();
+        assert_eq!(sum.to_string(), "(in0 + 7)");
+        let product = x.clone().checked_mul(Expr::Const(5), &search).unwrap();
+        let product = Expr::Const(2).checked_mul(product, &search).unwrap();
+        assert_eq!(product.to_string(), "(in0 * 10)");
+        assert_eq!(
+            x.clone().checked_mul(Expr::Const(0), &search),
+            Some(Expr::Const(0))
+        );
+        assert_eq!(
+            Expr::Const(0).checked_add(x.clone(), &search),
+            Some(x.clone())
+        );
+        assert_eq!(
+            Expr::Const(i64::MAX).checked_add(Expr::Const(1), &search),
+            None
+        );
+        assert_eq!(x.clone().equals(x.clone(), &search), Expr::Const(1));
+        let less = x.less_than(Expr::Const(8));
+        assert_eq!(less.clone().equals(Expr::Const(2), &search), Expr::Const(0));
+        assert_eq!(
+            sum.clone().equals(Expr::Const(9), &search).to_string(),
+            "(in0 == 2)"
+        );
+        assert_eq!(less.evaluate(&[7]), Some(1));
+        assert_eq!(sum.evaluate(&[]), None);
+    }
+
+    #[test]
+    fn test_overflow() {
+        // in0 + (MAX - 50) overflows for in0 above 50, so adding -100 after
+        // it must not be folded in.
+        let x = Expr::Input(0);
+        let big = x
+            .clone()
+            .checked_add(Expr::Const(i64::MAX - 50), &(-100..=100))
+            .unwrap();
+        let sum = big
+            .clone()
+            .checked_add(Expr::Const(-100), &(-100..=100))
+            .unwrap();
+        assert_eq!(sum.to_string(), "((in0 + 9223372036854775757) + -100)");
+        assert_eq!(
+            (sum.evaluate(&[50]), sum.evaluate(&[51])),
+            (Some(i64::MAX - 100), None)
+        );
+        let sum = big
+            .clone()
+            .checked_add(Expr::Const(-100), &(-100..=50))
+            .unwrap();
+        assert_eq!(sum.to_string(), "(in0 + 9223372036854775657)");
+
+        // Comparisons keep what can overflow, so the machine failing on
+        // in0 = 51 does not meet the constraint.
+        let same = big.clone().equals(big.clone(), &(-100..=100));
+        assert_eq!(
+            (same.evaluate(&[50]), same.evaluate(&[51])),
+            (Some(1), None)
+        );
+        let equal = big.clone().equals(Expr::Const(i64::MAX), &(-100..=100));
+        assert_eq!(
+            equal.to_string(),
+            "((in0 + 9223372036854775757) == 9223372036854775807)"
+        );
+        assert_eq!(equal.evaluate(&[51]), None);
+        assert_eq!(
+            big.clone().equals(big.clone(), &(-100..=50)),
+            Expr::Const(1)
+        );
+        let equal = big.equals(Expr::Const(i64::MAX), &(-100..=50));
+        assert_eq!(equal.to_string(), "(in0 == 50)");
+
+        // -in0 overflows for in0 = MIN, while in0 * 1 does not.
+        let negated = x.checked_mul(Expr::Const(-1), &(-5..=5)).unwrap();
+        let product = negated.clone().checked_mul(Expr::Const(-1), &(-5..=5));
+        assert_eq!(product.unwrap().to_string(), "(in0 * 1)");
+        let product = negated
+            .checked_mul(Expr::Const(-1), &(i64::MIN..=0))
+            .unwrap();
+        assert_eq!(product.to_string(), "((in0 * -1) * -1)");
+        assert_eq!(product.evaluate(&[i64::MIN]), None);
+        assert_eq!(product.bounds(&(i64::MIN..=0)), None);
+    }
+
+    #[test]
+    fn test_branches() {
+        // Outputs 999 if the input is below 8, 1000 if it is 8, and 1001 if
+        // it is above, from the day 5 examples. It computes 1000 as the
+        // input times 125.
+        let program = vec![
+            3, 21, 1008, 21, 8, 20, 1005, 20, 22, 107, 8, 21, 20, 1006, 20, 31, 1106, 0, 36, 98, 0,
+            0, 1002, 21, 125, 20, 4, 20, 1105, 1, 46, 104, 999, 1105, 1, 46, 1101, 1000, 1, 20, 4,
+            20, 1105, 1, 46, 98, 99,
+        ];
+        let executor = SymbolicExecutor::new(program.clone());
+        let paths = executor.explore();
+        assert_eq!(paths.len(), 3);
+        for path in &paths {
+            assert_eq!(
+                (&path.end, path.inputs, path.outputs.len()),
+                (&End::Halted, 1, 1)
+            );
+            // Every input the constraints allow gives the output of the path.
+            let output = &path.outputs[0];
+            let input = executor.solve(&path.constraints, 1).unwrap();
+            let mut out = Vec::new();
+            Machine::new(program.clone())
+                .run_to_halt(&mut VecDeque::from(input.clone()), &mut out)
+                .unwrap();
+            assert_eq!(Some(out[0]), output.value.evaluate(&input));
+        }
+        let text: Vec<_> = paths
+            .iter()
+            .map(|path| {
+                let constraints: Vec<_> = path.constraints.iter().map(|c| c.to_string()).collect();
+                format!("{} => {}", constraints.join(", "), path.outputs[0].value)
+            })
+            .collect();
+        assert_eq!(
+            text,
+            [
+                "in0 == 8 => (in0 * 125)",
+                "in0 != 8, 8 < in0 => 1001",
+                "in0 != 8, 8 >= in0 => 999",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_expressions() {
+        let program = assemble(
+            "
+                in -> [x]
+                in -> [y]
+                mul [x], [y] -> [p]
+                add [p], #-12 -> [p]
+                out [p]
+                hlt
+            x: .data 0
+            y: .data 0
+            p: .data 0
+            ",
+        )
+        .unwrap();
+        let executor = SymbolicExecutor::new(program);
+        let paths = executor.explore();
+        assert_eq!(paths.len(), 1);
+        let output = &paths[0].outputs[0];
+        assert_eq!(output.value.to_string(), "((in0 * in1) + -12)");
+        let zero = Constraint::equals(output.value.clone(), 0, &(-100..=100));
+        assert_eq!(executor.solve(&[zero], 2), Some(vec![-12, -1]));
+    }
+
+    #[test]
+    fn test_limits() {
+        let program = assemble("loop: in -> [x]\njz [x], loop\nhlt\nx: .data 0").unwrap();
+        let paths = SymbolicExecutor::new(program.clone())
+            .max_inputs(3)
+            .explore();
+        let ends: Vec<_> = paths
+            .iter()
+            .map(|path| (path.inputs, path.end.clone()))
+            .collect();
+        assert_eq!(
+            ends,
+            [
+                (1, End::Halted),
+                (2, End::Halted),
+                (3, End::Halted),
+                (3, End::InputLimit)
+            ]
+        );
+        let paths = SymbolicExecutor::new(program).max_paths(2).explore();
+        assert_eq!(paths.last().unwrap().end, End::PathLimit);
+
+        let paths = SymbolicExecutor::new(vec![1105, 1, 0])
+            .max_steps(10)
+            .explore();
+        assert_eq!(paths[0].end, End::StepLimit);
+        let paths = SymbolicExecutor::new(vec![3, 3, 4, 0, 99]).explore();
+        assert!(matches!(paths[0].end, End::Unresolved(2)));
+        // Forks on every address in the search range instead.
+        let paths = SymbolicExecutor::new(vec![3, 3, 4, 0, 99])
+            .max_forks(201)
+            .explore();
+        assert_eq!(paths.len(), 201);
+        let paths = SymbolicExecutor::new(vec![1, 0, 0, -1]).explore();
+        match &paths[0].end {
+            End::Error(err) => assert_eq!(err.kind, ErrorKind::NegativeAddress(-1)),
+            end => panic!("unexpected {:?}", end),
+        }
+    }
+
+    #[test]
+    fn test_diagnostic() {
+        // The first input is added to an instruction word, so the paths fork
+        // on every input that makes it a valid instruction.
+        let program = load("../day_05/input.txt").unwrap();
+        let executor = SymbolicExecutor::new(program.clone());
+        let paths = executor.explore();
+        let passing: Vec<_> = paths
+            .iter()
+            .filter(|path| path.end == End::Halted)
+            .filter(|path| match path.outputs.split_last() {
+                Some((_, checks)) => checks.iter().all(|o| o.value == Expr::Const(0)),
+                None => false,
+            })
+            .filter_map(|path| executor.solve(&path.constraints, path.inputs))
+            .collect();
+        assert!(passing.contains(&vec![1]));
+        assert!(passing.contains(&vec![5]));
+        for input in passing {
+            let mut out = Vec::new();
+            Machine::new(program.clone())
+                .run_to_halt(&mut VecDeque::from(input), &mut out)
+                .unwrap();
+            assert!(out[..out.len() - 1].iter().all(|&o| o == 0));
+        }
+    }
+}
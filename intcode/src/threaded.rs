@@ -0,0 +1,371 @@
+use crate::{
+    error::{Error, ErrorKind},
+    io::{Input, Output},
+    machine::{Machine, Status},
+    memory::MemoryLimitExceeded,
+    parameter::{address, Parameter, ParameterMode},
+    word::Word,
+};
+
+use std::{convert::TryFrom, fmt, ops::Deref, sync::Arc};
+
+/// An argument with its mode and value decoded ahead of time.
+#[derive(Debug, Clone, Copy)]
+enum Operand<W> {
+    Immediate(W),
+    Position(Result<usize, ErrorKind>),
+    Relative(W),
+}
+
+impl<W: Word> Operand<W> {
+    fn new(mode: ParameterMode, value: W) -> Self {
+        match mode {
+            ParameterMode::PositionMode => Operand::Position(address(value)),
+            ParameterMode::ImmediateMode => Operand::Immediate(value),
+            ParameterMode::RelativeMode => Operand::Relative(value),
+        }
+    }
+
+    fn address(self, machine: &Machine<W>) -> Result<usize, ErrorKind> {
+        match self {
+            Operand::Immediate(_) => Err(ErrorKind::WriteInImmediateMode),
+            Operand::Position(address) => address,
+            Operand::Relative(offset) => address(
+                machine
+                    .relative_base
+                    .checked_add(offset)
+                    .ok_or(ErrorKind::ArithmeticOverflow)?,
+            ),
+        }
+    }
+
+    fn read(self, machine: &Machine<W>) -> Result<W, ErrorKind> {
+        match self {
+            Operand::Immediate(value) => Ok(value),
+            _ => Ok(machine.memory.get(self.address(machine)?)?),
+        }
+    }
+}
+
+/// What a decoded instruction did: the status it left the machine in, and
+/// the address it wrote to, if any.
+type Effect = Result<(Status, Option<usize>), ErrorKind>;
+
+type Run<W> =
+    dyn Fn(&mut Machine<W>, &mut dyn Input<W>, &mut dyn Output<W>) -> Effect + Send + Sync;
+
+/// An instruction turned into a closure over its decoded arguments.
+struct Decoded<W> {
+    len: usize,
+    run: Box<Run<W>>,
+}
+
+/// Stores the result of a two argument instruction at `to`.
+fn store<W: Word>(
+    at: usize,
+    x: Operand<W>,
+    y: Operand<W>,
+    to: Operand<W>,
+    op: fn(W, W) -> Option<W>,
+) -> Box<Run<W>> {
+    Box::new(move |machine, _, _| {
+        let value = op(x.read(machine)?, y.read(machine)?).ok_or(ErrorKind::ArithmeticOverflow)?;
+        let target = to.address(machine)?;
+        machine.memory.set(target, value)?;
+        machine.ip = at + 4;
+        Ok((Status::Running, Some(target)))
+    })
+}
+
+/// Jumps to `to` if `x` being zero is `if_zero`.
+fn jump<W: Word>(at: usize, x: Operand<W>, to: Operand<W>, if_zero: bool) -> Box<Run<W>> {
+    Box::new(move |machine, _, _| {
+        machine.ip = match (x.read(machine)? == *W::ZERO) == if_zero {
+            true => address(to.read(machine)?)?,
+            false => at + 3,
+        };
+        Ok((Status::Running, None))
+    })
+}
+
+/// Decodes the instruction at address `at`, which starts `words`.
+///
+/// `None` if the instruction is unknown or runs past the end of `words`.
+fn decode<W: Word>(words: &[W], at: usize) -> Option<Decoded<W>> {
+    let word = i64::try_from(words[0].to_i128()).ok()?;
+    let param = Parameter::try_from(word).ok()?;
+    let len = param.arg_count()? + 1;
+    let args = words.get(1..len)?;
+    let modes = param.modes();
+    let arg = |n: usize| Operand::new(modes[n], args[n]);
+    let run: Box<Run<W>> = match param.opcode {
+        1 => store(at, arg(0), arg(1), arg(2), W::checked_add),
+        2 => store(at, arg(0), arg(1), arg(2), W::checked_mul),
+        3 => {
+            let to = arg(0);
+            Box::new(move |machine, inn, _| match inn.read_value()? {
+                Some(value) => {
+                    let target = to.address(machine)?;
+                    machine.memory.set(target, value)?;
+                    machine.ip = at + 2;
+                    Ok((Status::Running, Some(target)))
+                }
+                None => Ok((Status::Blocking, None)),
+            })
+        }
+        4 => {
+            let x = arg(0);
+            Box::new(move |machine, _, out| {
+                out.write_value(x.read(machine)?)?;
+                machine.ip = at + 2;
+                Ok((Status::Running, None))
+            })
+        }
+        5 => jump(at, arg(0), arg(1), false),
+        6 => jump(at, arg(0), arg(1), true),
+        7 => store(at, arg(0), arg(1), arg(2), |x, y| {
+            Some(W::from_i64((x < y) as i64))
+        }),
+        8 => store(at, arg(0), arg(1), arg(2), |x, y| {
+            Some(W::from_i64((x == y) as i64))
+        }),
+        9 => {
+            let x = arg(0);
+            Box::new(move |machine, _, _| {
+                machine.relative_base = machine
+                    .relative_base
+                    .checked_add(x.read(machine)?)
+                    .ok_or(ErrorKind::ArithmeticOverflow)?;
+                machine.ip = at + 2;
+                Ok((Status::Running, None))
+            })
+        }
+        99 => Box::new(|_, _, _| Ok((Status::Halted, None))),
+        _ => return None,
+    };
+    Some(Decoded { len, run })
+}
+
+/// A machine running a program decoded ahead of time.
+///
+/// Every address of the loaded program is decoded once, into a closure that
+/// has its arguments and modes baked in, so running an instruction skips
+/// decoding altogether. Clones share the decoded program. When the program
+/// writes into a decoded instruction, that clone stops using it and decodes
+/// the instruction as it runs, like a plain [`Machine`], so self-modifying
+/// programs behave exactly the same.
+#[derive(Clone)]
+pub struct ThreadedMachine<W = i64> {
+    machine: Machine<W>,
+    decoded: Arc<[Option<Decoded<W>>]>,
+    /// Decoded instructions that have been written to, empty until the
+    /// first one is.
+    stale: Vec<bool>,
+}
+
+impl<W: Word> fmt::Debug for ThreadedMachine<W> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ThreadedMachine")
+            .field("machine", &self.machine)
+            .finish()
+    }
+}
+
+impl<W: Word> From<Machine<W>> for ThreadedMachine<W> {
+    fn from(machine: Machine<W>) -> Self {
+        let (words, _) = machine.memory.to_parts();
+        let decoded = (0..words.len())
+            .map(|at| decode(&words[at..], at))
+            .collect();
+        Self {
+            machine,
+            decoded,
+            stale: Vec::new(),
+        }
+    }
+}
+
+/// Gives read access to the wrapped machine; writes go through
+/// [`ThreadedMachine::set`] so they can invalidate decoded instructions.
+impl<W> Deref for ThreadedMachine<W> {
+    type Target = Machine<W>;
+
+    fn deref(&self) -> &Machine<W> {
+        &self.machine
+    }
+}
+
+impl<W: Word> ThreadedMachine<W> {
+    pub fn into_machine(self) -> Machine<W> {
+        self.machine
+    }
+
+    pub fn push_input(&mut self, value: W) {
+        self.machine.push_input(value);
+    }
+
+    /// Writes to memory, invalidating any decoded instruction covering `address`.
+    pub fn set(&mut self, address: usize, value: W) -> Result<(), MemoryLimitExceeded> {
+        self.machine.memory.set(address, value)?;
+        self.invalidate(address);
+        Ok(())
+    }
+
+    /// Marks the decoded instructions that `address` is part of as stale.
+    fn invalidate(&mut self, address: usize) {
+        let end = (address + 1).min(self.decoded.len());
+        for at in address.saturating_sub(3)..end {
+            if let Some(decoded) = &self.decoded[at] {
+                if at + decoded.len > address {
+                    self.stale.resize(self.decoded.len(), false);
+                    self.stale[at] = true;
+                }
+            }
+        }
+    }
+
+    /// Runs a single instruction, like [`Machine::step`].
+    pub fn step<I: Input<W> + ?Sized, O: Output<W> + ?Sized>(
+        &mut self,
+        inn: &mut I,
+        out: &mut O,
+    ) -> Result<Status, Error> {
+        if self.machine.status == Status::Halted {
+            return Ok(Status::Halted);
+        }
+        let ip = self.machine.ip;
+        let stale = self.stale.get(ip).copied().unwrap_or(false);
+        let decoded = match self.decoded.get(ip) {
+            Some(Some(decoded)) if !stale => decoded,
+            // Beyond the loaded program, unknown, or written to.
+            _ => return self.step_undecoded(inn, out),
+        };
+        let (mut inn, mut out) = (inn, out);
+        match (decoded.run)(&mut self.machine, &mut inn, &mut out) {
+            Ok((status, written)) => {
+                self.machine.status = status;
+                if let Some(address) = written {
+                    self.invalidate(address);
+                }
+                Ok(status)
+            }
+            Err(kind) => Err(self.machine.error(ip, kind)),
+        }
+    }
+
+    /// Runs the instruction at `ip` on the wrapped machine, which decodes it.
+    fn step_undecoded<I: Input<W> + ?Sized, O: Output<W> + ?Sized>(
+        &mut self,
+        inn: &mut I,
+        out: &mut O,
+    ) -> Result<Status, Error> {
+        let machine = &self.machine;
+        let written = machine
+            .memory
+            .get(machine.ip)
+            .ok()
+            .and_then(|word| i64::try_from(word.to_i128()).ok())
+            .and_then(|word| Parameter::try_from(word).ok())
+            .and_then(|param| {
+                param.write_address(machine.ip, &machine.memory, machine.relative_base)
+            });
+        let status = self.machine.step(inn, out)?;
+        if let Some(address) = written {
+            self.invalidate(address);
+        }
+        Ok(status)
+    }
+
+    /// Runs until the machine halts or blocks on input, like [`Machine::run`].
+    pub fn run<I: Input<W> + ?Sized, O: Output<W> + ?Sized>(
+        &mut self,
+        inn: &mut I,
+        out: &mut O,
+    ) -> Result<Status, Error> {
+        loop {
+            match self.step(inn, out)? {
+                Status::Running => (),
+                status => return Ok(status),
+            }
+        }
+    }
+
+    /// Runs until the machine halts, like [`Machine::run_to_halt`].
+    pub fn run_to_halt<I: Input<W> + ?Sized, O: Output<W> + ?Sized>(
+        &mut self,
+        inn: &mut I,
+        out: &mut O,
+    ) -> Result<(), Error> {
+        match self.run(inn, out)? {
+            Status::Blocking => Err(self
+                .machine
+                .error(self.machine.ip, ErrorKind::InputExhausted)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::loader::parse_program;
+
+    use std::{collections::VecDeque, fs::read_to_string};
+
+    fn both(program: &[i64], input: &[i64]) -> (Result<Vec<i64>, Error>, ThreadedMachine) {
+        let mut machine = Machine::new(program.to_vec());
+        let mut threaded = ThreadedMachine::from(machine.clone());
+        let mut out = Vec::new();
+        let expected = machine
+            .run_to_halt(&mut VecDeque::from(input.to_vec()), &mut out)
+            .map(|_| out);
+        let mut out = Vec::new();
+        let actual = threaded
+            .run_to_halt(&mut VecDeque::from(input.to_vec()), &mut out)
+            .map(|_| out);
+        assert_eq!(actual, expected);
+        assert_eq!(threaded.memory(), machine.memory());
+        (actual, threaded)
+    }
+
+    #[test]
+    fn test_self_modification() {
+        // Rewrites the argument, then the opcode, of a decoded instruction.
+        assert_eq!(both(&[1101, 40, 2, 5, 104, 0, 99], &[]).0, Ok(vec![42]));
+        assert_eq!(both(&[1101, 100, 4, 4, 99, 9, 99], &[]).0, Ok(vec![9]));
+
+        let mut machine = ThreadedMachine::from(Machine::new(vec![104, 1, 99]));
+        let clone = machine.clone();
+        machine.set(1, 2).unwrap();
+        assert!(Arc::ptr_eq(&machine.decoded, &clone.decoded));
+        assert_eq!((machine.stale[0], clone.stale.is_empty()), (true, true));
+        let mut out = Vec::new();
+        machine.run(&mut VecDeque::new(), &mut out).unwrap();
+        assert_eq!(out, vec![2]);
+    }
+
+    #[test]
+    fn test_errors() {
+        let (result, machine) = both(&[1, -1, 0, 0, 99], &[]);
+        assert_eq!(result.unwrap_err().kind, ErrorKind::NegativeAddress(-1));
+        assert_eq!(machine.status(), Status::NotYetStarted);
+        assert_eq!(both(&[109, 5, 203, -5, 4, 0, 99], &[4]).0, Ok(vec![4]));
+        assert_eq!(
+            both(&[3, 0, 55], &[7]).0.unwrap_err().kind,
+            ErrorKind::UnknownOpcode
+        );
+    }
+
+    #[test]
+    fn test_puzzle_inputs() {
+        let program = parse_program(&read_to_string("../day_05/input.txt").unwrap()).unwrap();
+        assert_eq!(both(&program, &[5]).0.unwrap().len(), 1);
+        both(&program, &[1]).0.unwrap();
+        let program = parse_program(&read_to_string("../day_07/input.txt").unwrap()).unwrap();
+        both(&program, &[4, 0]).0.unwrap();
+        assert_eq!(
+            both(&program, &[9, 0, 7]).0.unwrap_err().kind,
+            ErrorKind::InputExhausted
+        );
+    }
+}
@@ -0,0 +1,552 @@
+// The impls generated by `err-derive` live inside an anonymous const.
+#![allow(non_local_definitions)]
+
+use crate::{
+    error::Error,
+    io::{Input, Output},
+    machine::{Machine, Status},
+    parameter::{Parameter, ParameterMode},
+    threaded::ThreadedMachine,
+};
+
+use err_derive::Error;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    convert::TryFrom,
+    fmt,
+    io::{self, BufRead, Write},
+};
+
+/// A value stored by an instruction.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub struct MemoryWrite {
+    pub address: usize,
+    pub value: i64,
+}
+
+/// Everything a single executed instruction did.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct TraceEntry {
+    pub ip: usize,
+    pub opcode: usize,
+    /// The mode of every argument, in the order they follow the instruction.
+    pub modes: Vec<ParameterMode>,
+    /// The values of the arguments the instruction reads.
+    pub reads: Vec<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub write: Option<MemoryWrite>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<i64>,
+    /// The error the instruction failed with, which ends the trace.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Remembers the value passed through to the wrapped input or output.
+struct Recorder<'a, T: ?Sized> {
+    inner: &'a mut T,
+    value: Option<i64>,
+}
+
+impl<T: Input + ?Sized> Input for Recorder<'_, T> {
+    fn read_value(&mut self) -> io::Result<Option<i64>> {
+        self.value = self.inner.read_value()?;
+        Ok(self.value)
+    }
+}
+
+impl<T: Output + ?Sized> Output for Recorder<'_, T> {
+    fn write_value(&mut self, value: i64) -> io::Result<()> {
+        self.value = Some(value);
+        self.inner.write_value(value)
+    }
+}
+
+/// An instruction that failed, and its trace entry.
+struct Failed {
+    err: Error,
+    entry: TraceEntry,
+}
+
+/// Like [`Traced::step_traced`], but an instruction that fails is described
+/// too, by an entry that records where and why it failed and the value it
+/// read or output before that. Its `opcode` is 0 if it does not decode.
+fn step_recorded<M: Traced, I: Input + ?Sized, O: Output + ?Sized>(
+    machine: &mut M,
+    inn: &mut I,
+    out: &mut O,
+) -> Result<(Status, Option<TraceEntry>), Box<Failed>> {
+    let mut inn = Recorder {
+        inner: inn,
+        value: None,
+    };
+    let mut out = Recorder {
+        inner: out,
+        value: None,
+    };
+    machine.step_traced(&mut inn, &mut out).map_err(|err| {
+        let opcode = i64::try_from(err.instruction)
+            .ok()
+            .and_then(|word| Parameter::try_from(word).ok())
+            .map_or(0, |param| param.opcode);
+        let entry = TraceEntry {
+            ip: err.ip,
+            opcode,
+            modes: Vec::new(),
+            reads: Vec::new(),
+            write: None,
+            input: inn.value,
+            output: out.value,
+            error: Some(err.to_string()),
+        };
+        Box::new(Failed { err, entry })
+    })
+}
+
+/// An interpreter that can be traced, and that a trace can be replayed on.
+pub trait Traced {
+    /// The state of the machine, read before and after every step.
+    fn machine(&self) -> &Machine;
+
+    /// Runs a single instruction, like [`Machine::step`].
+    fn step<I: Input + ?Sized, O: Output + ?Sized>(
+        &mut self,
+        inn: &mut I,
+        out: &mut O,
+    ) -> Result<Status, Error>;
+
+    /// Like [`Traced::step`], but also describes the executed instruction.
+    ///
+    /// No entry is returned when nothing was executed, that is when the
+    /// machine had already halted or is blocking on input.
+    fn step_traced<I: Input + ?Sized, O: Output + ?Sized>(
+        &mut self,
+        inn: &mut I,
+        out: &mut O,
+    ) -> Result<(Status, Option<TraceEntry>), Error> {
+        let machine = self.machine();
+        if machine.status() == Status::Halted {
+            return Ok((Status::Halted, None));
+        }
+        let ip = machine.ip();
+        let relative_base = machine.relative_base();
+        let memory = machine.memory();
+        let param = Parameter::try_from(memory[ip]).ok();
+        let write = param.and_then(|param| param.write_address(ip, memory, relative_base));
+        // Arguments are read before running the instruction, which may
+        // overwrite them. If they cannot be read, `step` fails below.
+        let (modes, reads) = match param.and_then(|param| param.arg_count().map(|n| (param, n))) {
+            Some((param, count)) => {
+                let modes = param.modes()[..count.min(3)].to_vec();
+                let reads = match param.writes() {
+                    true => &modes[..count - 1],
+                    false => &modes[..],
+                };
+                let reads = reads
+                    .iter()
+                    .enumerate()
+                    .map(|(n, mode)| {
+                        mode.get_ip(ip + 1 + n, memory, relative_base)
+                            .unwrap_or_default()
+                    })
+                    .collect();
+                (modes, reads)
+            }
+            None => (Vec::new(), Vec::new()),
+        };
+        let mut inn = Recorder {
+            inner: inn,
+            value: None,
+        };
+        let mut out = Recorder {
+            inner: out,
+            value: None,
+        };
+        let status = self.step(&mut inn, &mut out)?;
+        if status == Status::Blocking {
+            return Ok((status, None));
+        }
+        let entry = TraceEntry {
+            ip,
+            // The instruction decoded fine, or `step` would have failed.
+            opcode: param.unwrap().opcode,
+            modes,
+            reads,
+            write: write.map(|address| MemoryWrite {
+                address,
+                value: self.machine().memory()[address],
+            }),
+            input: inn.value,
+            output: out.value,
+            error: None,
+        };
+        Ok((status, Some(entry)))
+    }
+}
+
+impl Traced for Machine {
+    fn machine(&self) -> &Machine {
+        self
+    }
+
+    fn step<I: Input + ?Sized, O: Output + ?Sized>(
+        &mut self,
+        inn: &mut I,
+        out: &mut O,
+    ) -> Result<Status, Error> {
+        Machine::step(self, inn, out)
+    }
+}
+
+impl Traced for ThreadedMachine {
+    fn machine(&self) -> &Machine {
+        self
+    }
+
+    fn step<I: Input + ?Sized, O: Output + ?Sized>(
+        &mut self,
+        inn: &mut I,
+        out: &mut O,
+    ) -> Result<Status, Error> {
+        ThreadedMachine::step(self, inn, out)
+    }
+}
+
+/// What went wrong while writing or reading a trace.
+#[derive(Debug, Error)]
+pub enum TraceError {
+    #[error(display = "{}", _0)]
+    Machine(Error),
+    #[error(display = "I/O error: {}", _0)]
+    Io(io::Error),
+    #[error(display = "line {}: {}", line, message)]
+    Parse { line: usize, message: String },
+}
+
+impl From<Error> for TraceError {
+    fn from(err: Error) -> Self {
+        TraceError::Machine(err)
+    }
+}
+
+impl From<io::Error> for TraceError {
+    fn from(err: io::Error) -> Self {
+        TraceError::Io(err)
+    }
+}
+
+/// Runs a machine while writing a trace of it as JSON Lines.
+#[derive(Debug)]
+pub struct Tracer<W> {
+    writer: W,
+    steps: usize,
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer, steps: 0 }
+    }
+
+    /// How many entries have been written.
+    pub fn steps(&self) -> usize {
+        self.steps
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Executes one instruction, writing its entry to the trace. An
+    /// instruction that fails ends the trace with an entry holding its error.
+    pub fn step<M: Traced, I: Input + ?Sized, O: Output + ?Sized>(
+        &mut self,
+        machine: &mut M,
+        inn: &mut I,
+        out: &mut O,
+    ) -> Result<Status, TraceError> {
+        let (status, entry) = match step_recorded(machine, inn, out) {
+            Ok(step) => step,
+            Err(failed) => {
+                self.write(&failed.entry)?;
+                return Err(failed.err.into());
+            }
+        };
+        if let Some(entry) = entry {
+            self.write(&entry)?;
+        }
+        Ok(status)
+    }
+
+    fn write(&mut self, entry: &TraceEntry) -> io::Result<()> {
+        serde_json::to_writer(&mut self.writer, entry)?;
+        writeln!(self.writer)?;
+        self.steps += 1;
+        Ok(())
+    }
+
+    /// Runs until the machine halts or blocks, like [`Machine::run`].
+    pub fn run<M: Traced, I: Input + ?Sized, O: Output + ?Sized>(
+        &mut self,
+        machine: &mut M,
+        inn: &mut I,
+        out: &mut O,
+    ) -> Result<Status, TraceError> {
+        loop {
+            match self.step(machine, inn, out)? {
+                Status::Running => (),
+                status => return Ok(status),
+            }
+        }
+    }
+}
+
+/// Reads a trace written by a [`Tracer`], ignoring blank lines.
+pub fn read_trace<R: BufRead>(reader: R) -> Result<Vec<TraceEntry>, TraceError> {
+    let mut entries = Vec::new();
+    for (n, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).map_err(|err| TraceError::Parse {
+            line: n + 1,
+            message: err.to_string(),
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// The first step where a fresh run did something else than the trace.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Divergence {
+    /// Index of the entry in the trace.
+    pub step: usize,
+    /// The traced entry, `None` if the fresh run kept going after the trace ended.
+    pub expected: Option<TraceEntry>,
+    /// What the fresh run did instead.
+    pub actual: Result<Option<TraceEntry>, Error>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let entry = |entry: &TraceEntry| serde_json::to_string(entry).unwrap();
+        writeln!(f, "divergence at step {}", self.step)?;
+        match &self.expected {
+            Some(expected) => writeln!(f, "expected: {}", entry(expected))?,
+            None => writeln!(f, "expected: end of trace")?,
+        }
+        match &self.actual {
+            Ok(Some(actual)) => write!(f, "actual:   {}", entry(actual)),
+            Ok(None) => write!(f, "actual:   machine stopped"),
+            Err(err) => write!(f, "actual:   {}", err),
+        }
+    }
+}
+
+impl std::error::Error for Divergence {}
+
+/// Runs a freshly loaded `machine`, checking every step against `trace`,
+/// which may have been recorded on another interpreter.
+///
+/// The fresh run is fed the inputs recorded in the trace. On success the
+/// number of matching steps is returned. A trace that ended with an error
+/// matches a fresh run failing with the same error at the same point.
+pub fn replay<M: Traced>(mut machine: M, trace: &[TraceEntry]) -> Result<usize, Box<Divergence>> {
+    let mut inn: VecDeque<_> = trace.iter().filter_map(|entry| entry.input).collect();
+    let mut out = Vec::new();
+    for (step, expected) in trace.iter().enumerate() {
+        let actual = step_recorded(&mut machine, &mut inn, &mut out);
+        let matches = match &actual {
+            Ok((_, Some(actual))) => actual == expected,
+            Err(failed) => failed.entry == *expected,
+            Ok((_, None)) => false,
+        };
+        match matches {
+            true if expected.error.is_some() => return Ok(step + 1),
+            true => (),
+            false => {
+                return Err(Box::new(Divergence {
+                    step,
+                    expected: Some(expected.clone()),
+                    actual: actual.map(|(_, entry)| entry).map_err(|failed| failed.err),
+                }))
+            }
+        }
+    }
+    match machine.step_traced(&mut inn, &mut out) {
+        Ok((_, None)) => Ok(trace.len()),
+        actual => Err(Box::new(Divergence {
+            step: trace.len(),
+            expected: None,
+            actual: actual.map(|(_, entry)| entry),
+        })),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm::assemble, error::ErrorKind};
+
+    fn program() -> Vec<i64> {
+        assemble(
+            "
+                arb #20
+                in -> [r+0]
+                mul [r+0], #3 -> [x]
+                out [x]
+                hlt
+            x:  .data 0
+            ",
+        )
+        .unwrap()
+    }
+
+    fn trace(program: Vec<i64>, input: i64) -> Vec<u8> {
+        let mut tracer = Tracer::new(Vec::new());
+        let mut machine = Machine::new(program);
+        let status = tracer
+            .run(
+                &mut machine,
+                &mut VecDeque::from(vec![input]),
+                &mut Vec::new(),
+            )
+            .unwrap();
+        assert_eq!(status, Status::Halted);
+        assert_eq!(tracer.steps(), 5);
+        tracer.into_inner()
+    }
+
+    #[test]
+    fn test_entries() {
+        let mut machine = Machine::new(program());
+        let mut out = Vec::new();
+        let (status, entry) = machine.step_traced(&mut VecDeque::new(), &mut out).unwrap();
+        assert_eq!(status, Status::Running);
+        assert_eq!(entry.unwrap().reads, vec![20]);
+        let (status, entry) = machine.step_traced(&mut VecDeque::new(), &mut out).unwrap();
+        assert_eq!((status, entry), (Status::Blocking, None));
+
+        let entries = read_trace(&trace(program(), 7)[..]).unwrap();
+        assert_eq!(
+            entries[1],
+            TraceEntry {
+                ip: 2,
+                opcode: 3,
+                modes: vec![ParameterMode::RelativeMode],
+                reads: vec![],
+                write: Some(MemoryWrite {
+                    address: 20,
+                    value: 7
+                }),
+                input: Some(7),
+                output: None,
+                error: None,
+            }
+        );
+        assert_eq!(entries[2].reads, vec![7, 3]);
+        assert_eq!(entries[2].write.unwrap().value, 21);
+        assert_eq!(entries[3].output, Some(21));
+        assert_eq!(entries[4].opcode, 99);
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let text = String::from_utf8(trace(program(), 7)).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert_eq!(
+            lines[3],
+            r#"{"ip":8,"opcode":4,"modes":["position"],"reads":[21],"output":21}"#
+        );
+        match read_trace("{\"ip\":0}\n".as_bytes()) {
+            Err(TraceError::Parse { line: 1, .. }) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    /// Multiplies one too many, but is otherwise a plain [`Machine`].
+    struct OffByOne(Machine);
+
+    impl Traced for OffByOne {
+        fn machine(&self) -> &Machine {
+            &self.0
+        }
+
+        fn step<I: Input + ?Sized, O: Output + ?Sized>(
+            &mut self,
+            inn: &mut I,
+            out: &mut O,
+        ) -> Result<Status, Error> {
+            let machine = &mut self.0;
+            let (ip, base) = (machine.ip(), machine.relative_base());
+            let param = Parameter::try_from(machine.memory()[ip]).unwrap();
+            let write = param.write_address(ip, machine.memory(), base);
+            let status = machine.step(inn, out)?;
+            if let (2, Some(address)) = (param.opcode, write) {
+                let value = machine.memory()[address];
+                machine.memory_mut().set(address, value + 1).unwrap();
+            }
+            Ok(status)
+        }
+    }
+
+    #[test]
+    fn test_replay() {
+        let entries = read_trace(&trace(program(), 7)[..]).unwrap();
+        assert_eq!(replay(Machine::new(program()), &entries), Ok(5));
+        let threaded = || ThreadedMachine::from(Machine::new(program()));
+        assert_eq!(replay(threaded(), &entries), Ok(5));
+
+        let divergence = replay(OffByOne(Machine::new(program())), &entries).unwrap_err();
+        assert_eq!(divergence.step, 2);
+        assert_eq!(divergence.actual.unwrap().unwrap().write.unwrap().value, 22);
+
+        let divergence = replay(threaded(), &entries[..3]).unwrap_err();
+        assert_eq!((divergence.step, divergence.expected), (3, None));
+    }
+
+    #[test]
+    fn test_failed_run() {
+        let mut failing = program();
+        failing[3] = -40;
+        let mut tracer = Tracer::new(Vec::new());
+        let mut machine = ThreadedMachine::from(Machine::new(failing.clone()));
+        let err = tracer
+            .run(&mut machine, &mut VecDeque::from(vec![7]), &mut Vec::new())
+            .unwrap_err();
+        assert!(matches!(err, TraceError::Machine(_)));
+        let entries = read_trace(&tracer.into_inner()[..]).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(
+            entries[1],
+            TraceEntry {
+                ip: 2,
+                opcode: 3,
+                modes: vec![],
+                reads: vec![],
+                write: None,
+                input: Some(7),
+                output: None,
+                error: Some("negative address -20 (ip: 2, instruction: 203)".into()),
+            }
+        );
+        assert_eq!(replay(Machine::new(failing.clone()), &entries), Ok(2));
+
+        // A run that fails differently, or not at all, diverges there.
+        failing[3] = -30;
+        let divergence = replay(Machine::new(failing), &entries).unwrap_err();
+        assert_eq!(divergence.step, 1);
+        assert_eq!(
+            divergence.actual.unwrap_err().kind,
+            ErrorKind::NegativeAddress(-10)
+        );
+        let divergence = replay(Machine::new(program()), &entries).unwrap_err();
+        assert_eq!(divergence.step, 1);
+        assert_eq!(divergence.actual.unwrap().unwrap().input, Some(7));
+    }
+}
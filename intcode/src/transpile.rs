@@ -0,0 +1,567 @@
+// The impls generated by `err-derive` live inside an anonymous const.
+#![allow(non_local_definitions)]
+
+use crate::{
+    cfg::{word, Block, Cfg, Exit, Instruction, Target},
+    parameter::ParameterMode,
+};
+
+use err_derive::Error;
+use std::{
+    collections::BTreeMap,
+    convert::TryFrom,
+    fmt::{self, Write},
+};
+
+/// Something in a program that the transpiler cannot turn into Rust.
+#[derive(Debug, Error, PartialEq, Eq, Clone)]
+pub enum Unsupported {
+    #[error(display = "{:04}: {} is not a valid instruction", address, word)]
+    InvalidInstruction { address: usize, word: i64 },
+    #[error(display = "{:04}: jumps to an address read from memory", _0)]
+    ComputedJump(usize),
+    #[error(display = "{:04}: jumps to negative address {}", address, target)]
+    NegativeJump { address: usize, target: i64 },
+    #[error(display = "{:04}: overlaps the instruction at {:04}", address, other)]
+    Overlap { address: usize, other: usize },
+    #[error(
+        display = "{:04}: writes to {}, inside the instruction at {:04}",
+        address,
+        target,
+        instruction
+    )]
+    SelfModification {
+        address: usize,
+        target: usize,
+        instruction: usize,
+    },
+    #[error(
+        display = "{:04}: writes relative to the relative base, which may land in code",
+        _0
+    )]
+    RelativeWrite(usize),
+    #[error(display = "{:04}: writes to an argument in immediate mode", _0)]
+    ImmediateWrite(usize),
+}
+
+impl Unsupported {
+    /// The address of the offending instruction.
+    pub fn address(&self) -> usize {
+        match *self {
+            Unsupported::InvalidInstruction { address, .. }
+            | Unsupported::NegativeJump { address, .. }
+            | Unsupported::Overlap { address, .. }
+            | Unsupported::SelfModification { address, .. }
+            | Unsupported::ComputedJump(address)
+            | Unsupported::RelativeWrite(address)
+            | Unsupported::ImmediateWrite(address) => address,
+        }
+    }
+}
+
+/// Why a program could not be transpiled, one entry per problem found.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct TranspileError(pub Vec<Unsupported>);
+
+impl fmt::Display for TranspileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cannot transpile the program:")?;
+        for problem in &self.0 {
+            write!(f, "\n    {}", problem)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for TranspileError {}
+
+/// Checks that the code reachable from address 0 can be transpiled,
+/// collecting every problem found.
+fn check(program: &[i64]) -> Result<Cfg, TranspileError> {
+    let cfg = Cfg::new(program);
+    let mut problems: Vec<_> = cfg
+        .invalid
+        .iter()
+        .map(|&address| Unsupported::InvalidInstruction {
+            address,
+            word: word(program, address),
+        })
+        .collect();
+    for block in cfg.blocks.values() {
+        let address = block.instructions.last().unwrap().address;
+        match block.exit {
+            Exit::Jump {
+                target: Target::Computed,
+                ..
+            } => problems.push(Unsupported::ComputedJump(address)),
+            Exit::Jump {
+                target: Target::Negative(target),
+                ..
+            } => problems.push(Unsupported::NegativeJump { address, target }),
+            _ => (),
+        }
+    }
+
+    let instructions: BTreeMap<_, _> = cfg
+        .instructions()
+        .map(|instruction| (instruction.address, instruction))
+        .collect();
+    let mut end = 0;
+    let mut last = 0;
+    for (&address, instruction) in &instructions {
+        if address < end {
+            problems.push(Unsupported::Overlap {
+                address,
+                other: last,
+            });
+        }
+        if address + instruction.len() > end {
+            end = address + instruction.len();
+            last = address;
+        }
+    }
+    for (&address, instruction) in &instructions {
+        if !instruction.param.writes() {
+            continue;
+        }
+        match instruction.arg(instruction.args.len() - 1) {
+            (ParameterMode::PositionMode, target) => {
+                let target = match usize::try_from(target) {
+                    Ok(target) => target,
+                    // Fails when it runs, without writing anything.
+                    Err(_) => continue,
+                };
+                let covering = instructions
+                    .range(..=target)
+                    .next_back()
+                    .filter(|(&at, other)| at + other.len() > target)
+                    .map(|(&at, _)| at);
+                if let Some(at) = covering.or_else(|| cfg.invalid.get(&target).copied()) {
+                    problems.push(Unsupported::SelfModification {
+                        address,
+                        target,
+                        instruction: at,
+                    });
+                }
+            }
+            (ParameterMode::RelativeMode, _) => problems.push(Unsupported::RelativeWrite(address)),
+            (ParameterMode::ImmediateMode, _) => {
+                problems.push(Unsupported::ImmediateWrite(address))
+            }
+        }
+    }
+    match problems.is_empty() {
+        true => Ok(cfg),
+        false => {
+            problems.sort_by_key(Unsupported::address);
+            problems.dedup();
+            Err(TranspileError(problems))
+        }
+    }
+}
+
+/// Helper functions of the generated module, emitted only when used.
+const HELPERS: &[(&str, &str)] = &[
+    (
+        "read(",
+        "
+fn read(m: &State, address: usize) -> Result<i64, ErrorKind> {
+    check(address)?;
+    Ok(m.memory.get(address).copied().unwrap_or(0))
+}
+",
+    ),
+    (
+        "write(",
+        "
+fn write(m: &mut State, address: usize, value: i64) -> Result<(), ErrorKind> {
+    check(address)?;
+    if address >= m.memory.len() {
+        m.memory.resize(address + 1, 0);
+    }
+    m.memory[address] = value;
+    Ok(())
+}
+",
+    ),
+    (
+        "check(",
+        "
+fn check(address: usize) -> Result<(), ErrorKind> {
+    match address < DEFAULT_MEMORY_LIMIT {
+        true => Ok(()),
+        false => Err(ErrorKind::MemoryLimit {
+            address,
+            limit: DEFAULT_MEMORY_LIMIT,
+        }),
+    }
+}
+",
+    ),
+    (
+        "relative(",
+        "
+fn relative(m: &State, offset: i64) -> Result<usize, ErrorKind> {
+    address(add(m.relative_base, offset)?)
+}
+",
+    ),
+    (
+        "address(",
+        "
+fn address(value: i64) -> Result<usize, ErrorKind> {
+    match value < 0 {
+        true => Err(ErrorKind::NegativeAddress(value.into())),
+        false => Ok(value as usize),
+    }
+}
+",
+    ),
+    (
+        "add(",
+        "
+fn add(x: i64, y: i64) -> Result<i64, ErrorKind> {
+    x.checked_add(y).ok_or(ErrorKind::ArithmeticOverflow)
+}
+",
+    ),
+    (
+        "mul(",
+        "
+fn mul(x: i64, y: i64) -> Result<i64, ErrorKind> {
+    x.checked_mul(y).ok_or(ErrorKind::ArithmeticOverflow)
+}
+",
+    ),
+    (
+        "input(",
+        "
+fn input(inn: &mut dyn Input) -> Result<i64, ErrorKind> {
+    inn.read_value()?.ok_or(ErrorKind::InputExhausted)
+}
+",
+    ),
+];
+
+/// The Rust expression reading an argument.
+fn read(mode: ParameterMode, value: i64) -> String {
+    match mode {
+        ParameterMode::ImmediateMode => value.to_string(),
+        ParameterMode::PositionMode => format!("read(m, {})?", target(value)),
+        ParameterMode::RelativeMode => format!("read(m, relative(m, {})?)?", value),
+    }
+}
+
+/// The Rust expression of a position mode address.
+fn target(value: i64) -> String {
+    match value < 0 {
+        true => format!("address({})?", value),
+        false => value.to_string(),
+    }
+}
+
+/// Writes the statements running `instruction`, leaving jumps and halts to
+/// the end of the block.
+fn emit(instruction: &Instruction, body: &mut String) -> fmt::Result {
+    writeln!(body, "    // {}", instruction)?;
+    writeln!(body, "    m.ip = {};", instruction.address)?;
+    let arg = |n: usize| {
+        let (mode, value) = instruction.arg(n);
+        read(mode, value)
+    };
+    let store = |body: &mut String, value: String| {
+        writeln!(body, "    let value = {};", value)?;
+        writeln!(
+            body,
+            "    write(m, {}, value)?;",
+            target(instruction.args[instruction.args.len() - 1])
+        )
+    };
+    match instruction.param.opcode {
+        1 => store(body, format!("add({}, {})?", arg(0), arg(1))),
+        2 => store(body, format!("mul({}, {})?", arg(0), arg(1))),
+        3 => store(body, "input(inn)?".to_string()),
+        4 => writeln!(body, "    out.write_value({})?;", arg(0)),
+        7 | 8 => {
+            let less = instruction.param.opcode == 7;
+            match (instruction.arg(0), instruction.arg(1)) {
+                // Folded, as comparing two literals trips lints.
+                ((ParameterMode::ImmediateMode, x), (ParameterMode::ImmediateMode, y)) => {
+                    let value = match less {
+                        true => x < y,
+                        false => x == y,
+                    };
+                    store(body, (value as i64).to_string())
+                }
+                _ => {
+                    let compare = match less {
+                        true => "<",
+                        false => "==",
+                    };
+                    store(body, format!("({} {} {}) as i64", arg(0), compare, arg(1)))
+                }
+            }
+        }
+        9 => writeln!(
+            body,
+            "    m.relative_base = add(m.relative_base, {})?;",
+            arg(0)
+        ),
+        _ => Ok(()),
+    }
+}
+
+/// Writes the statements leaving `block`, once its instructions have run.
+fn emit_exit(block: &Block, body: &mut String) -> fmt::Result {
+    match block.exit {
+        Exit::Fallthrough(next) => writeln!(body, "    Ok(Some({}))", next),
+        Exit::Halt => writeln!(body, "    Ok(None)"),
+        Exit::Jump {
+            target: Target::Address(target),
+            next,
+        } => match next {
+            Some(next) => {
+                let jump = block.instructions.last().unwrap();
+                let compare = match jump.param.opcode {
+                    5 => "!=",
+                    _ => "==",
+                };
+                let (mode, value) = jump.arg(0);
+                writeln!(body, "    if {} {} 0 {{", read(mode, value), compare)?;
+                writeln!(body, "        return Ok(Some({}));", target)?;
+                writeln!(body, "    }}")?;
+                writeln!(body, "    Ok(Some({}))", next)
+            }
+            None => writeln!(body, "    Ok(Some({}))", target),
+        },
+        // Refused by `check`.
+        Exit::Jump { .. } => unreachable!(),
+    }
+}
+
+/// The name of the function running the block starting at `address`.
+fn block_name(address: usize) -> String {
+    format!("block_{:04}", address)
+}
+
+/// Turns a program that never writes into its own code into a standalone
+/// Rust module.
+///
+/// The code is recovered from address 0 by following both edges of every
+/// conditional jump, which must have an immediate target. Every write must
+/// be in position mode and land outside the code, so the instructions never
+/// change and each basic block becomes a plain Rust function. The module's
+/// `run` function runs the program with an [`Input`](crate::Input) and
+/// [`Output`](crate::Output), failing with the same [`Error`](crate::Error)
+/// a [`Machine`](crate::Machine) would, and returns the final memory.
+pub fn transpile(program: &[i64]) -> Result<String, TranspileError> {
+    let cfg = check(program)?;
+    let mut blocks = String::new();
+    let mut dispatch = String::new();
+    for (&leader, block) in &cfg.blocks {
+        let mut body = String::new();
+        for instruction in &block.instructions {
+            emit(instruction, &mut body).unwrap();
+        }
+        emit_exit(block, &mut body).unwrap();
+        let unused = |used| match used {
+            true => "",
+            false => "_",
+        };
+        writeln!(
+            blocks,
+            "\nfn {}(\n    m: &mut State,\n    {}inn: &mut dyn Input,\n    {}out: &mut dyn Output,\n) -> Result<Option<usize>, ErrorKind> {{\n{}}}",
+            block_name(leader),
+            unused(body.contains("input(inn)")),
+            unused(body.contains("out.write_value")),
+            body,
+        )
+        .unwrap();
+        writeln!(
+            dispatch,
+            "            {} => {}(&mut m, inn, out),",
+            leader,
+            block_name(leader)
+        )
+        .unwrap();
+    }
+
+    let mut module = String::new();
+    writeln!(
+        module,
+        "// Transpiled from a {} word Intcode program, see `intcode::transpile`.",
+        program.len()
+    )
+    .unwrap();
+    module.push_str(
+        "
+use intcode::{Error, ErrorKind, Input, Output, DEFAULT_MEMORY_LIMIT};
+
+",
+    );
+    write!(module, "const PROGRAM: [i64; {}] = [", program.len()).unwrap();
+    for (n, value) in program.iter().enumerate() {
+        match n % 10 {
+            0 => write!(module, "\n    {},", value),
+            _ => write!(module, " {},", value),
+        }
+        .unwrap();
+    }
+    module.push_str(
+        "
+];
+
+struct State {
+    memory: Vec<i64>,
+    ip: usize,
+    relative_base: i64,
+}
+
+/// Runs the program to completion, returning its final memory.
+pub fn run(inn: &mut dyn Input, out: &mut dyn Output) -> Result<Vec<i64>, Error> {
+    let mut m = State {
+        memory: PROGRAM.to_vec(),
+        ip: 0,
+        relative_base: 0,
+    };
+    let mut block = 0;
+    loop {
+        let next = match block {
+",
+    );
+    module.push_str(&dispatch);
+    module.push_str(
+        "            _ => unreachable!(),
+        };
+        match next {
+            Ok(Some(next)) => block = next,
+            Ok(None) => return Ok(m.memory),
+            Err(kind) => {
+                return Err(Error {
+                    ip: m.ip,
+                    instruction: PROGRAM.get(m.ip).copied().unwrap_or(0).into(),
+                    kind,
+                })
+            }
+        }
+    }
+}
+",
+    );
+    let mut used = blocks.clone();
+    for (name, helper) in HELPERS {
+        if used.contains(name) {
+            module.push_str(helper);
+            used.push_str(helper);
+        }
+    }
+    module.push_str(&blocks);
+    Ok(module)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{asm::assemble, machine::Machine};
+
+    use std::collections::VecDeque;
+
+    mod transpiled {
+        include!("../testdata/transpiled.rs");
+    }
+
+    /// Outputs the running maximum of its input until it reads zero, then
+    /// the maximum times the count.
+    const SOURCE: &str = "
+            arb #count
+        loop:
+            in -> [x]
+            jz [x], end
+            add [r+0], #1 -> [count]
+            lt [max], [x] -> [t]
+            jz [t], skip
+            add [x], #0 -> [max]
+        skip:
+            out [max]
+            jnz #1, loop
+        end:
+            mul [max], [r+0] -> [x]
+            out [x]
+            hlt
+        x:      .data 0
+        t:      .data 0
+        max:    .data 0
+        count:  .data 0
+    ";
+
+    #[test]
+    fn test_transpiled() {
+        let program = assemble(SOURCE).unwrap();
+        assert_eq!(
+            transpile(&program).unwrap(),
+            include_str!("../testdata/transpiled.rs")
+        );
+        let inputs = [
+            vec![0],
+            vec![3, 1, 4, 1, 5, 0],
+            vec![3, 1],
+            vec![i64::MAX, 2, 0],
+        ];
+        for input in &inputs {
+            let mut machine = Machine::new(program.clone());
+            let mut out = Vec::new();
+            let expected = machine
+                .run_to_halt(&mut VecDeque::from(input.clone()), &mut out)
+                .map(|_| out);
+            let mut out = Vec::new();
+            let actual = transpiled::run(&mut VecDeque::from(input.clone()), &mut out);
+            if let Ok(memory) = &actual {
+                let cells: Vec<_> = (0..memory.len()).map(|a| machine.memory()[a]).collect();
+                assert_eq!(memory, &cells);
+            }
+            assert_eq!(actual.map(|_| out), expected);
+        }
+    }
+
+    #[test]
+    fn test_refusals() {
+        let problems = |source| transpile(&assemble(source).unwrap()).unwrap_err().0;
+        assert_eq!(
+            problems("in -> [2]\nhlt"),
+            vec![Unsupported::SelfModification {
+                address: 0,
+                target: 2,
+                instruction: 2
+            }]
+        );
+        assert_eq!(
+            problems("add [r+1], #1 -> [r+2]\nhlt"),
+            vec![Unsupported::RelativeWrite(0)]
+        );
+        // Jumps into its own argument.
+        assert_eq!(
+            transpile(&[1005, 4, 1, 99, 0]).unwrap_err().0,
+            vec![Unsupported::Overlap {
+                address: 1,
+                other: 0
+            }]
+        );
+        assert_eq!(
+            problems("in -> [9]\njz [9], [10]\nhlt\n.data 0, 0, 0\n.data 42"),
+            vec![Unsupported::ComputedJump(2)]
+        );
+        // Jumps that are never taken need no target.
+        assert!(transpile(&assemble("jz #1, [10]\nhlt").unwrap()).is_ok());
+
+        let err = transpile(&[1105, 1, -2, 1106, 0, 7, 0, 55]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot transpile the program:\n    0000: jumps to negative address -2"
+        );
+        let err = transpile(&[1106, 0, 3, 55]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "cannot transpile the program:\n    0003: 55 is not a valid instruction"
+        );
+    }
+}
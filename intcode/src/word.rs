@@ -0,0 +1,102 @@
+use std::{fmt, num::ParseIntError, num::Wrapping, str::FromStr};
+
+/// The integer type a [`Machine`](crate::Machine) computes with.
+///
+/// `i64` and `i128` are checked: an addition or multiplication that
+/// overflows stops the machine with
+/// [`ErrorKind::ArithmeticOverflow`](crate::ErrorKind::ArithmeticOverflow),
+/// reporting the faulting instruction. `Wrapping<i64>` and `Wrapping<i128>`
+/// wrap around instead, and never fail.
+pub trait Word: Copy + Default + Ord + fmt::Debug + fmt::Display + Send + Sync + 'static {
+    /// What memory that has never been written reads as.
+    const ZERO: &'static Self;
+
+    fn from_i64(value: i64) -> Self;
+
+    /// Widens the word, which holds the value of every provided word type.
+    fn to_i128(self) -> i128;
+
+    /// `None` if the sum overflows.
+    fn checked_add(self, other: Self) -> Option<Self>;
+
+    /// `None` if the product overflows.
+    fn checked_mul(self, other: Self) -> Option<Self>;
+
+    /// Parses the decimal text form of a word.
+    fn parse(text: &str) -> Result<Self, ParseIntError>;
+}
+
+macro_rules! impl_word {
+    ($type:ty) => {
+        impl Word for $type {
+            const ZERO: &'static Self = &0;
+
+            fn from_i64(value: i64) -> Self {
+                value.into()
+            }
+
+            fn to_i128(self) -> i128 {
+                self.into()
+            }
+
+            fn checked_add(self, other: Self) -> Option<Self> {
+                <$type>::checked_add(self, other)
+            }
+
+            fn checked_mul(self, other: Self) -> Option<Self> {
+                <$type>::checked_mul(self, other)
+            }
+
+            fn parse(text: &str) -> Result<Self, ParseIntError> {
+                <$type>::from_str(text)
+            }
+        }
+
+        impl Word for Wrapping<$type> {
+            const ZERO: &'static Self = &Wrapping(0);
+
+            fn from_i64(value: i64) -> Self {
+                Wrapping(value.into())
+            }
+
+            fn to_i128(self) -> i128 {
+                self.0.into()
+            }
+
+            fn checked_add(self, other: Self) -> Option<Self> {
+                Some(self + other)
+            }
+
+            fn checked_mul(self, other: Self) -> Option<Self> {
+                Some(self * other)
+            }
+
+            fn parse(text: &str) -> Result<Self, ParseIntError> {
+                <$type>::from_str(text).map(Wrapping)
+            }
+        }
+    };
+}
+
+impl_word!(i64);
+impl_word!(i128);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_overflow() {
+        assert_eq!(Word::checked_mul(i64::MAX, 2), None);
+        assert_eq!(
+            Word::checked_mul(i64::MAX as i128, 2),
+            Some(i64::MAX as i128 * 2)
+        );
+        assert_eq!(
+            Word::checked_add(Wrapping(i64::MAX), Wrapping(1)),
+            Some(Wrapping(i64::MIN))
+        );
+        assert_eq!(<Wrapping<i128> as Word>::parse("-12"), Ok(Wrapping(-12)));
+        assert_eq!(Wrapping(-3i64).to_i128(), -3);
+    }
+}
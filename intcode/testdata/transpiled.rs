@@ -0,0 +1,186 @@
+// Transpiled from a 38 word Intcode program, see `intcode::transpile`.
+
+use intcode::{Error, ErrorKind, Input, Output, DEFAULT_MEMORY_LIMIT};
+
+const PROGRAM: [i64; 38] = [
+    109, 37, 3, 34, 1006, 34, 27, 1201, 0, 1,
+    37, 7, 36, 34, 35, 1006, 35, 22, 1001, 34,
+    0, 36, 4, 36, 1105, 1, 2, 2002, 36, 0,
+    34, 4, 34, 99, 0, 0, 0, 0,
+];
+
+struct State {
+    memory: Vec<i64>,
+    ip: usize,
+    relative_base: i64,
+}
+
+/// Runs the program to completion, returning its final memory.
+pub fn run(inn: &mut dyn Input, out: &mut dyn Output) -> Result<Vec<i64>, Error> {
+    let mut m = State {
+        memory: PROGRAM.to_vec(),
+        ip: 0,
+        relative_base: 0,
+    };
+    let mut block = 0;
+    loop {
+        let next = match block {
+            0 => block_0000(&mut m, inn, out),
+            2 => block_0002(&mut m, inn, out),
+            7 => block_0007(&mut m, inn, out),
+            18 => block_0018(&mut m, inn, out),
+            22 => block_0022(&mut m, inn, out),
+            27 => block_0027(&mut m, inn, out),
+            _ => unreachable!(),
+        };
+        match next {
+            Ok(Some(next)) => block = next,
+            Ok(None) => return Ok(m.memory),
+            Err(kind) => {
+                return Err(Error {
+                    ip: m.ip,
+                    instruction: PROGRAM.get(m.ip).copied().unwrap_or(0).into(),
+                    kind,
+                })
+            }
+        }
+    }
+}
+
+fn read(m: &State, address: usize) -> Result<i64, ErrorKind> {
+    check(address)?;
+    Ok(m.memory.get(address).copied().unwrap_or(0))
+}
+
+fn write(m: &mut State, address: usize, value: i64) -> Result<(), ErrorKind> {
+    check(address)?;
+    if address >= m.memory.len() {
+        m.memory.resize(address + 1, 0);
+    }
+    m.memory[address] = value;
+    Ok(())
+}
+
+fn check(address: usize) -> Result<(), ErrorKind> {
+    match address < DEFAULT_MEMORY_LIMIT {
+        true => Ok(()),
+        false => Err(ErrorKind::MemoryLimit {
+            address,
+            limit: DEFAULT_MEMORY_LIMIT,
+        }),
+    }
+}
+
+fn relative(m: &State, offset: i64) -> Result<usize, ErrorKind> {
+    address(add(m.relative_base, offset)?)
+}
+
+fn address(value: i64) -> Result<usize, ErrorKind> {
+    match value < 0 {
+        true => Err(ErrorKind::NegativeAddress(value.into())),
+        false => Ok(value as usize),
+    }
+}
+
+fn add(x: i64, y: i64) -> Result<i64, ErrorKind> {
+    x.checked_add(y).ok_or(ErrorKind::ArithmeticOverflow)
+}
+
+fn mul(x: i64, y: i64) -> Result<i64, ErrorKind> {
+    x.checked_mul(y).ok_or(ErrorKind::ArithmeticOverflow)
+}
+
+fn input(inn: &mut dyn Input) -> Result<i64, ErrorKind> {
+    inn.read_value()?.ok_or(ErrorKind::InputExhausted)
+}
+
+fn block_0000(
+    m: &mut State,
+    _inn: &mut dyn Input,
+    _out: &mut dyn Output,
+) -> Result<Option<usize>, ErrorKind> {
+    // 0000: ARB #37
+    m.ip = 0;
+    m.relative_base = add(m.relative_base, 37)?;
+    Ok(Some(2))
+}
+
+fn block_0002(
+    m: &mut State,
+    inn: &mut dyn Input,
+    _out: &mut dyn Output,
+) -> Result<Option<usize>, ErrorKind> {
+    // 0002: IN -> [34]
+    m.ip = 2;
+    let value = input(inn)?;
+    write(m, 34, value)?;
+    // 0004: JZ [34], #27
+    m.ip = 4;
+    if read(m, 34)? == 0 {
+        return Ok(Some(27));
+    }
+    Ok(Some(7))
+}
+
+fn block_0007(
+    m: &mut State,
+    _inn: &mut dyn Input,
+    _out: &mut dyn Output,
+) -> Result<Option<usize>, ErrorKind> {
+    // 0007: ADD [r+0], #1 -> [37]
+    m.ip = 7;
+    let value = add(read(m, relative(m, 0)?)?, 1)?;
+    write(m, 37, value)?;
+    // 0011: LT [36], [34] -> [35]
+    m.ip = 11;
+    let value = (read(m, 36)? < read(m, 34)?) as i64;
+    write(m, 35, value)?;
+    // 0015: JZ [35], #22
+    m.ip = 15;
+    if read(m, 35)? == 0 {
+        return Ok(Some(22));
+    }
+    Ok(Some(18))
+}
+
+fn block_0018(
+    m: &mut State,
+    _inn: &mut dyn Input,
+    _out: &mut dyn Output,
+) -> Result<Option<usize>, ErrorKind> {
+    // 0018: ADD [34], #0 -> [36]
+    m.ip = 18;
+    let value = add(read(m, 34)?, 0)?;
+    write(m, 36, value)?;
+    Ok(Some(22))
+}
+
+fn block_0022(
+    m: &mut State,
+    _inn: &mut dyn Input,
+    out: &mut dyn Output,
+) -> Result<Option<usize>, ErrorKind> {
+    // 0022: OUT [36]
+    m.ip = 22;
+    out.write_value(read(m, 36)?)?;
+    // 0024: JNZ #1, #2
+    m.ip = 24;
+    Ok(Some(2))
+}
+
+fn block_0027(
+    m: &mut State,
+    _inn: &mut dyn Input,
+    out: &mut dyn Output,
+) -> Result<Option<usize>, ErrorKind> {
+    // 0027: MUL [36], [r+0] -> [34]
+    m.ip = 27;
+    let value = mul(read(m, 36)?, read(m, relative(m, 0)?)?)?;
+    write(m, 34, value)?;
+    // 0031: OUT [34]
+    m.ip = 31;
+    out.write_value(read(m, 34)?)?;
+    // 0033: HLT
+    m.ip = 33;
+    Ok(None)
+}